@@ -0,0 +1,342 @@
+//! Generates the escrow program's argument structs, account structs,
+//! instruction builders and account fetchers from its Anchor IDL, plus the
+//! `accounts` and `instruction` modules anchor_client request builders take.
+//! Argument and account structs also derive serde, see `src/json.rs`.
+//!
+//! The output is included by `src/escrow.rs` and `src/program.rs`, so a field
+//! added to the IDL shows up as a compile error wherever the affected type is
+//! built by hand. Account fetchers decode through a hand-written
+//! `decode_<account>` in scope where they are included, since layouts older
+//! than the IDL cannot be generated from it.
+
+use anchor_lang_idl_spec::{
+    Idl, IdlArrayLen, IdlDefinedFields, IdlField, IdlInstruction, IdlInstructionAccountItem,
+    IdlType, IdlTypeDef, IdlTypeDefTy,
+};
+use std::{env, fmt::Write, fs, path::Path};
+
+const IDL_PATH: &str = "idl/subscription_escrow.json";
+
+/// `u64` fields holding lamports, which the IDL doesn't distinguish from
+/// other integers. Their JSON form shows the amount in SOL as well.
+const LAMPORT_FIELDS: &[&str] = &["amount", "total_amount"];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", IDL_PATH);
+    let json = fs::read_to_string(IDL_PATH).expect("failed to read the escrow IDL");
+    let idl: Idl = serde_json::from_str(&json).expect("failed to parse the escrow IDL");
+
+    let header = format!(
+        "// Generated by build.rs from {} ({} {}). Do not edit.\n",
+        IDL_PATH, idl.metadata.name, idl.metadata.version
+    );
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    let mut out = header.clone();
+    for instruction in &idl.instructions {
+        generate_instruction(&mut out, instruction);
+    }
+    for type_def in &idl.types {
+        generate_type(&mut out, &idl, type_def);
+    }
+    for account in &idl.accounts {
+        generate_fetcher(&mut out, &account.name);
+    }
+
+    let path = Path::new(&out_dir).join("escrow_generated.rs");
+    fs::write(path, out).expect("failed to write the generated escrow client");
+
+    let mut out = header;
+    generate_anchor_modules(&mut out, &idl);
+    let path = Path::new(&out_dir).join("program_generated.rs");
+    fs::write(path, out).expect("failed to write the generated anchor_client modules");
+}
+
+/// Discriminator constant, argument struct and builder for an instruction.
+/// The builder takes the program ID, then every account without a fixed
+/// address in IDL order, then the arguments.
+fn generate_instruction(out: &mut String, instruction: &IdlInstruction) {
+    let name = &instruction.name;
+    let discriminator = format!("{}_DISCRIMINATOR", name.to_uppercase());
+    let args_struct = format!("{}Args", pascal_case(name));
+    let has_args = !instruction.args.is_empty();
+
+    writeln!(
+        out,
+        "\n/// Sighash prefixing `{}` instruction data.\npub const {}: [u8; 8] = {:?};",
+        name, discriminator, instruction.discriminator
+    )
+    .unwrap();
+
+    if has_args {
+        writeln!(
+            out,
+            "\n/// Arguments of the `{}` instruction.\n#[derive(anchor_lang::AnchorSerialize, anchor_lang::AnchorDeserialize, serde::Serialize, serde::Deserialize)]\npub struct {} {{",
+            name, args_struct
+        )
+        .unwrap();
+        write_fields(out, &instruction.args);
+        out.push_str("}\n");
+    }
+
+    let mut params = vec!["program_id: &Pubkey".to_string()];
+    let mut metas = Vec::new();
+    for item in &instruction.accounts {
+        let IdlInstructionAccountItem::Single(account) = item else {
+            panic!("{} uses composite accounts, which are not supported", name);
+        };
+        let key = match &account.address {
+            Some(address) => format!("solana_sdk::pubkey!(\"{}\")", address),
+            None => {
+                params.push(format!("{}: &Pubkey", account.name));
+                format!("*{}", account.name)
+            }
+        };
+        let constructor = if account.writable {
+            "new"
+        } else {
+            "new_readonly"
+        };
+        metas.push(format!(
+            "            AccountMeta::{}({}, {}),",
+            constructor, key, account.signer
+        ));
+    }
+
+    let (returns, data, wrap) = if has_args {
+        params.push(format!("args: &{}", args_struct));
+        (
+            "std::io::Result<Instruction>",
+            format!("instruction_data(&{}, args)?", discriminator),
+            ("Ok(", ")"),
+        )
+    } else {
+        (
+            "Instruction",
+            format!("{}.to_vec()", discriminator),
+            ("", ""),
+        )
+    };
+    writeln!(
+        out,
+        "\npub fn {}(\n    {},\n) -> {} {{\n    {}Instruction {{\n        program_id: *program_id,\n        accounts: vec![\n{}\n        ],\n        data: {},\n    }}{}\n}}",
+        name,
+        params.join(",\n    "),
+        returns,
+        wrap.0,
+        metas.join("\n"),
+        data,
+        wrap.1
+    )
+    .unwrap();
+}
+
+/// Struct for a type definition. Program accounts get `#[account]`, which
+/// supplies their discriminator and owner check.
+fn generate_type(out: &mut String, idl: &Idl, type_def: &IdlTypeDef) {
+    let IdlTypeDefTy::Struct { fields } = &type_def.ty else {
+        panic!("{} is not a struct, which is not supported", type_def.name);
+    };
+    let fields = match fields {
+        Some(IdlDefinedFields::Named(fields)) => fields.as_slice(),
+        Some(IdlDefinedFields::Tuple(_)) => {
+            panic!(
+                "{} is a tuple struct, which is not supported",
+                type_def.name
+            )
+        }
+        None => &[],
+    };
+
+    out.push('\n');
+    for doc in &type_def.docs {
+        writeln!(out, "/// {}", doc).unwrap();
+    }
+    if idl
+        .accounts
+        .iter()
+        .any(|account| account.name == type_def.name)
+    {
+        out.push_str(
+            "#[anchor_lang::account]\n#[derive(Default, serde::Serialize, serde::Deserialize)]\n",
+        );
+    } else {
+        out.push_str("#[derive(anchor_lang::AnchorSerialize, anchor_lang::AnchorDeserialize, serde::Serialize, serde::Deserialize)]\n");
+    }
+    writeln!(out, "pub struct {} {{", type_def.name).unwrap();
+    write_fields(out, fields);
+    out.push_str("}\n");
+}
+
+fn generate_fetcher(out: &mut String, account: &str) {
+    writeln!(
+        out,
+        "\n/// Fetches a `{account}` in any known layout, returning `None` once it has been closed.
+pub fn fetch_{snake}(
+    client: &RpcClient,
+    address: &Pubkey,
+) -> Result<Option<{account}>, Box<dyn std::error::Error>> {{
+    match client
+        .get_account_with_commitment(address, client.commitment())?
+        .value
+    {{
+        Some(account) => Ok(Some(decode_{snake}(&account.data)?.account)),
+        None => Ok(None),
+    }}
+}}",
+        account = account,
+        snake = snake_case(account)
+    )
+    .unwrap();
+}
+
+/// One struct per instruction in each of `accounts`, implementing
+/// `ToAccountMetas`, and `instruction`, implementing `InstructionData`, the
+/// way Anchor's own client code generation lays them out.
+fn generate_anchor_modules(out: &mut String, idl: &Idl) {
+    out.push_str(
+        "\n/// Accounts of each instruction, for `RequestBuilder::accounts`.\npub mod accounts {\n    use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};\n",
+    );
+    for instruction in &idl.instructions {
+        let name = pascal_case(&instruction.name);
+        let accounts: Vec<_> = instruction
+            .accounts
+            .iter()
+            .map(|item| match item {
+                IdlInstructionAccountItem::Single(account) => account,
+                IdlInstructionAccountItem::Composite(_) => panic!(
+                    "{} uses composite accounts, which are not supported",
+                    instruction.name
+                ),
+            })
+            .collect();
+
+        writeln!(out, "\n    pub struct {} {{", name).unwrap();
+        for account in &accounts {
+            writeln!(out, "        pub {}: Pubkey,", account.name).unwrap();
+        }
+        writeln!(
+            out,
+            "    }}\n\n    impl anchor_lang::ToAccountMetas for {} {{\n        fn to_account_metas(&self, is_signer: Option<bool>) -> Vec<AccountMeta> {{\n            vec![",
+            name
+        )
+        .unwrap();
+        for account in &accounts {
+            let constructor = if account.writable {
+                "new"
+            } else {
+                "new_readonly"
+            };
+            let signer = if account.signer {
+                "true".to_string()
+            } else {
+                "is_signer.unwrap_or(false)".to_string()
+            };
+            writeln!(
+                out,
+                "                AccountMeta::{}(self.{}, {}),",
+                constructor, account.name, signer
+            )
+            .unwrap();
+        }
+        out.push_str("            ]\n        }\n    }\n");
+    }
+    out.push_str("}\n");
+
+    out.push_str(
+        "\n/// Arguments of each instruction, for `RequestBuilder::args`.\npub mod instruction {\n    #[allow(unused_imports)]\n    use crate::escrow::*;\n    #[allow(unused_imports)]\n    use solana_sdk::pubkey::Pubkey;\n",
+    );
+    for instruction in &idl.instructions {
+        let name = pascal_case(&instruction.name);
+        writeln!(
+            out,
+            "\n    #[derive(anchor_lang::AnchorSerialize)]\n    pub struct {} {{",
+            name
+        )
+        .unwrap();
+        for field in &instruction.args {
+            writeln!(out, "        pub {}: {},", field.name, rust_type(&field.ty)).unwrap();
+        }
+        writeln!(
+            out,
+            "    }}\n\n    impl anchor_lang::Discriminator for {name} {{\n        const DISCRIMINATOR: [u8; 8] = {:?};\n    }}\n\n    impl anchor_lang::InstructionData for {name} {{}}",
+            instruction.discriminator,
+            name = name
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+}
+
+/// Struct fields, with the serde helpers from `crate::json` for pubkeys and
+/// lamport amounts.
+fn write_fields(out: &mut String, fields: &[IdlField]) {
+    for field in fields {
+        for doc in &field.docs {
+            writeln!(out, "    /// {}", doc).unwrap();
+        }
+        match field.ty {
+            IdlType::Pubkey => out.push_str("    #[serde(with = \"crate::json::pubkey\")]\n"),
+            IdlType::U64 if LAMPORT_FIELDS.contains(&field.name.as_str()) => {
+                out.push_str("    #[serde(with = \"crate::json::lamports\")]\n")
+            }
+            _ => {}
+        }
+        writeln!(out, "    pub {}: {},", field.name, rust_type(&field.ty)).unwrap();
+    }
+}
+
+fn rust_type(ty: &IdlType) -> String {
+    match ty {
+        IdlType::Bool => "bool".into(),
+        IdlType::U8 => "u8".into(),
+        IdlType::I8 => "i8".into(),
+        IdlType::U16 => "u16".into(),
+        IdlType::I16 => "i16".into(),
+        IdlType::U32 => "u32".into(),
+        IdlType::I32 => "i32".into(),
+        IdlType::F32 => "f32".into(),
+        IdlType::U64 => "u64".into(),
+        IdlType::I64 => "i64".into(),
+        IdlType::F64 => "f64".into(),
+        IdlType::U128 => "u128".into(),
+        IdlType::I128 => "i128".into(),
+        IdlType::Bytes => "Vec<u8>".into(),
+        IdlType::String => "String".into(),
+        IdlType::Pubkey => "Pubkey".into(),
+        IdlType::Option(inner) => format!("Option<{}>", rust_type(inner)),
+        IdlType::Vec(inner) => format!("Vec<{}>", rust_type(inner)),
+        IdlType::Array(inner, IdlArrayLen::Value(len)) => {
+            format!("[{}; {}]", rust_type(inner), len)
+        }
+        IdlType::Defined { name, generics } if generics.is_empty() => name.clone(),
+        other => panic!("IDL type {:?} is not supported", other),
+    }
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -0,0 +1,213 @@
+use crate::escrow::LAMPORTS_PER_SOL;
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+/// Most accounts getMultipleAccounts accepts per request.
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Lamports held by the seller, escrow and buyer of a subscription.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Balance {
+    #[serde(with = "crate::json::lamports")]
+    pub seller: u64,
+    #[serde(with = "crate::json::lamports")]
+    pub escrow: u64,
+    #[serde(with = "crate::json::lamports")]
+    pub buyer: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountBalance {
+    pub label: String,
+    #[serde(with = "crate::json::pubkey")]
+    pub address: Pubkey,
+    /// Zero for accounts that don't exist
+    #[serde(with = "crate::json::lamports")]
+    pub lamports: u64,
+}
+
+/// Balances of every tracked account at a named point of a run.
+#[derive(Clone, Debug, Serialize)]
+pub struct BalanceSnapshot {
+    pub label: String,
+    pub accounts: Vec<AccountBalance>,
+}
+
+impl BalanceSnapshot {
+    pub fn lamports(&self, account: &str) -> Option<u64> {
+        self.accounts
+            .iter()
+            .find(|balance| balance.label == account)
+            .map(|balance| balance.lamports)
+    }
+
+    pub fn print(&self) {
+        println!("\n=== Balances at {} ===", self.label);
+        for balance in &self.accounts {
+            println!(
+                "{}: {} SOL",
+                balance.label,
+                format_sol_balance(balance.lamports)
+            );
+        }
+        println!("========================\n");
+    }
+}
+
+/// Signed change of each tracked account between two snapshots.
+pub struct BalanceDelta {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<(String, i64)>,
+}
+
+impl BalanceDelta {
+    pub fn get(&self, account: &str) -> Option<i64> {
+        self.changes
+            .iter()
+            .find(|(label, _)| label == account)
+            .map(|&(_, change)| change)
+    }
+
+    pub fn print(&self) {
+        println!(
+            "\n=== Balance changes from {} to {} ===",
+            self.from, self.to
+        );
+        for (label, change) in &self.changes {
+            println!("{}: {} SOL", label, format_sol(*change));
+        }
+        println!("========================\n");
+    }
+
+    /// Fails unless `account` changed by `expected` lamports, give or take
+    /// `tolerance`.
+    pub fn expect(&self, account: &str, expected: i64, tolerance: u64) -> Result<(), String> {
+        let actual = self
+            .get(account)
+            .ok_or_else(|| format!("{} is not tracked", account))?;
+        if actual.abs_diff(expected) > tolerance {
+            return Err(format!(
+                "{} balance changed by {} SOL from {} to {}, expected {} SOL{}",
+                account,
+                format_sol(actual),
+                self.from,
+                self.to,
+                format_sol(expected),
+                format_tolerance(tolerance)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Snapshots the balances of a labelled set of accounts, each with a single
+/// getMultipleAccounts request, and compares snapshots.
+pub struct BalanceTracker {
+    accounts: Vec<(String, Pubkey)>,
+    snapshots: Vec<BalanceSnapshot>,
+}
+
+impl BalanceTracker {
+    pub fn new<'a>(accounts: impl IntoIterator<Item = (&'a str, Pubkey)>) -> Self {
+        Self {
+            accounts: accounts
+                .into_iter()
+                .map(|(label, address)| (label.to_string(), address))
+                .collect(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Fetches the current balance of every tracked account.
+    pub fn snapshot(
+        &mut self,
+        client: &RpcClient,
+        label: &str,
+    ) -> Result<&BalanceSnapshot, Box<dyn std::error::Error>> {
+        let addresses: Vec<Pubkey> = self.accounts.iter().map(|(_, address)| *address).collect();
+        let mut fetched = Vec::with_capacity(addresses.len());
+        for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            fetched.extend(client.get_multiple_accounts(chunk)?);
+        }
+        let accounts = self
+            .accounts
+            .iter()
+            .zip(fetched)
+            .map(|((label, address), account)| AccountBalance {
+                label: label.clone(),
+                address: *address,
+                lamports: account.map_or(0, |account| account.lamports),
+            })
+            .collect();
+        self.snapshots.push(BalanceSnapshot {
+            label: label.to_string(),
+            accounts,
+        });
+        Ok(self.snapshots.last().unwrap())
+    }
+
+    /// Changes between the snapshots labelled `from` and `to`.
+    pub fn delta(&self, from: &str, to: &str) -> Result<BalanceDelta, String> {
+        let find = |label: &str| {
+            self.snapshots
+                .iter()
+                .rev()
+                .find(|snapshot| snapshot.label == label)
+                .ok_or_else(|| format!("No balance snapshot labelled {}", label))
+        };
+        Ok(Self::compare(find(from)?, find(to)?))
+    }
+
+    /// Changes between the last two snapshots.
+    pub fn last_delta(&self) -> Result<BalanceDelta, String> {
+        match self.snapshots.as_slice() {
+            [.., from, to] => Ok(Self::compare(from, to)),
+            _ => Err("Balance deltas need at least two snapshots".to_string()),
+        }
+    }
+
+    /// Fails unless `account` changed by exactly `expected` lamports between
+    /// the last two snapshots.
+    pub fn expect_delta(&self, account: &str, expected: i64) -> Result<(), String> {
+        self.last_delta()?.expect(account, expected, 0)
+    }
+
+    fn compare(from: &BalanceSnapshot, to: &BalanceSnapshot) -> BalanceDelta {
+        BalanceDelta {
+            from: from.label.clone(),
+            to: to.label.clone(),
+            changes: from
+                .accounts
+                .iter()
+                .zip(&to.accounts)
+                .map(|(before, after)| {
+                    (
+                        before.label.clone(),
+                        after.lamports as i64 - before.lamports as i64,
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Lamports as SOL with an explicit sign.
+pub fn format_sol(lamports: i64) -> String {
+    format!("{:+}", lamports as f64 / LAMPORTS_PER_SOL as f64)
+}
+
+/// Lamports as SOL, for balances and amounts that are never negative.
+pub fn format_sol_balance(lamports: u64) -> String {
+    format!("{}", lamports as f64 / LAMPORTS_PER_SOL as f64)
+}
+
+/// " ± x SOL" for a non-zero tolerance in lamports, empty otherwise.
+pub fn format_tolerance(tolerance: u64) -> String {
+    if tolerance > 0 {
+        format!(" ± {} SOL", format_sol_balance(tolerance))
+    } else {
+        String::new()
+    }
+}
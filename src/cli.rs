@@ -0,0 +1,734 @@
+use crate::{
+    balances::format_sol_balance,
+    decoder,
+    escrow::{
+        self, EscrowAccount, EscrowFilter, MakePaymentArgs, StartSubscriptionArgs,
+        WithdrawFundsArgs,
+    },
+    harness::{self, HarnessOptions},
+    history, idl,
+    indexer::{self, Indexer},
+    nonce,
+    signing::PartialTransaction,
+    simulation,
+    submit::{self, Commitment, RetryOptions, RetryPolicy},
+    transaction::{self, TransactionOptions},
+    watch::{self, EscrowWatcher, Role},
+};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const RPC_URL: &str = "http://localhost:8899";
+
+/// Test harness and operator CLI for the subscription escrow program.
+#[derive(Parser)]
+pub struct Cli {
+    /// JSON RPC URL of the cluster
+    #[arg(long, global = true, default_value = RPC_URL)]
+    url: String,
+
+    /// Escrow program ID
+    #[arg(long, global = true, default_value_t = crate::ID)]
+    program_id: Pubkey,
+
+    /// Websocket URL for the subscriptions of test, watch and index sync
+    /// --follow. Derived from --url when omitted, on the next port as a local
+    /// test validator serves it
+    #[arg(long, global = true)]
+    ws_url: Option<String>,
+
+    /// Commitment level used for reads and for confirming transactions.
+    /// Transaction history is read at confirmed or higher
+    #[arg(long, global = true, value_enum, default_value_t)]
+    commitment: Commitment,
+
+    /// Runs the test suite when omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the end-to-end test suite against the cluster
+    Test(HarnessOptions),
+    /// Start a subscription, signed by the buyer
+    Start {
+        /// Buyer keypair file
+        #[arg(long)]
+        buyer: String,
+        /// Seller public key
+        #[arg(long)]
+        seller: Pubkey,
+        #[arg(long)]
+        subscription_id: String,
+        #[arg(long, default_value_t = escrow::DEFAULT_VALIDATION_THRESHOLD)]
+        validation_threshold: u64,
+        #[command(flatten)]
+        send: SendOptions,
+    },
+    /// Make a payment on a subscription, signed by the buyer
+    Pay {
+        /// Buyer keypair file
+        #[arg(long)]
+        buyer: String,
+        /// Seller public key
+        #[arg(long)]
+        seller: Pubkey,
+        #[arg(long)]
+        subscription_id: String,
+        /// Payment amount in lamports
+        #[arg(long)]
+        amount: u64,
+        #[command(flatten)]
+        send: SendOptions,
+    },
+    /// Cancel a subscription, signed by the buyer
+    Cancel {
+        /// Buyer keypair file
+        #[arg(long)]
+        buyer: String,
+        /// Seller public key
+        #[arg(long)]
+        seller: Pubkey,
+        #[arg(long)]
+        subscription_id: String,
+        #[command(flatten)]
+        send: SendOptions,
+    },
+    /// Withdraw escrowed funds, signed by the seller
+    Withdraw {
+        /// Seller keypair file
+        #[arg(long)]
+        seller: String,
+        /// Buyer public key
+        #[arg(long)]
+        buyer: Pubkey,
+        #[arg(long)]
+        subscription_id: String,
+        #[arg(long)]
+        validation_data: u64,
+        #[command(flatten)]
+        send: SendOptions,
+    },
+    /// List escrow accounts owned by the program
+    List {
+        /// Only list subscriptions paid by this buyer
+        #[arg(long)]
+        buyer: Option<Pubkey>,
+        /// Only list subscriptions paying this seller
+        #[arg(long)]
+        seller: Option<Pubkey>,
+        /// Only list active (true) or cancelled (false) subscriptions
+        #[arg(long)]
+        is_active: Option<bool>,
+        /// Also export the accounts to a CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Print the accounts as JSON instead of a table
+        #[arg(long, conflicts_with = "csv")]
+        json: bool,
+    },
+    /// Decode the escrow instructions of a transaction
+    Decode {
+        /// Signature of a confirmed transaction, or a base64-encoded transaction
+        transaction: String,
+    },
+    /// Show the payments, cancellations and withdrawals recorded for an escrow
+    History {
+        /// Escrow account address
+        escrow: Pubkey,
+    },
+    /// Check the client's instruction and account types against the program IDL
+    CheckIdl {
+        /// IDL file to check against, such as one generated by `anchor build`.
+        /// Defaults to the IDL bundled with the client
+        #[arg(long)]
+        idl: Option<PathBuf>,
+    },
+    /// Index escrow transactions into a local SQLite database and query it
+    Index {
+        /// SQLite database file
+        #[arg(long, default_value = "escrow-index.db")]
+        db: PathBuf,
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// Stream changes to a subscription's escrow account, buyer and seller
+    Watch {
+        /// Buyer public key
+        #[arg(long)]
+        buyer: Pubkey,
+        /// Seller public key
+        #[arg(long)]
+        seller: Pubkey,
+        #[arg(long)]
+        subscription_id: String,
+        /// Stop once the subscription is cancelled
+        #[arg(long)]
+        until_inactive: bool,
+    },
+    /// Create a durable nonce account for offline signing
+    CreateNonceAccount {
+        /// Keypair file paying for the account
+        #[arg(long)]
+        payer: String,
+        /// Nonce authority, defaults to the payer
+        #[arg(long)]
+        authority: Option<Pubkey>,
+    },
+    /// Add a signature to a partially signed transaction and print it
+    Sign {
+        /// Base64-encoded partially signed transaction
+        transaction: String,
+        /// Keypair file of a required signer
+        #[arg(long)]
+        keypair: String,
+    },
+    /// Send a transaction previously signed with --sign-only or --fee-payer
+    Submit {
+        /// Base64-encoded signed transaction
+        transaction: String,
+        /// Simulate the transaction and print its logs before sending it
+        #[arg(long)]
+        simulate: bool,
+        #[command(flatten)]
+        retry: RetryOptions,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Backfill from transaction history since the previous sync, optionally
+    /// following new transactions
+    Sync {
+        /// Only index transactions referencing this escrow account
+        #[arg(long)]
+        escrow: Option<Pubkey>,
+        /// Keep indexing transactions as they are confirmed
+        #[arg(long)]
+        follow: bool,
+    },
+    /// List open subscriptions
+    Open {
+        /// Only list subscriptions paying this seller
+        #[arg(long)]
+        seller: Option<Pubkey>,
+    },
+    /// Show the total currently held in escrow
+    Escrowed,
+    /// List refunds issued to buyers by failed withdrawals
+    Refunds,
+}
+
+#[derive(Args)]
+struct SendOptions {
+    /// Simulate the transaction and print its logs before sending it
+    #[arg(long)]
+    simulate: bool,
+
+    /// Durable nonce account to sign against instead of a recent blockhash.
+    /// The signing keypair must be the nonce authority
+    #[arg(long)]
+    nonce: Option<Pubkey>,
+
+    /// Nonce value to sign with, so that signing needs no RPC access
+    #[arg(long, requires = "nonce")]
+    nonce_blockhash: Option<Hash>,
+
+    /// Print the signed transaction as base64 instead of sending it
+    #[arg(long, requires = "nonce")]
+    sign_only: bool,
+
+    /// Fee payer other than the signing keypair. The transaction is printed
+    /// partially signed, for the fee payer to complete with `sign`
+    #[arg(long)]
+    fee_payer: Option<Pubkey>,
+
+    #[command(flatten)]
+    transaction: TransactionOptions,
+
+    #[command(flatten)]
+    retry: RetryOptions,
+}
+
+pub async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let client = RpcClient::new_with_commitment(cli.url.clone(), cli.commitment.config());
+    let program_id = cli.program_id;
+    // Only commands that subscribe need a websocket URL, so a --url the
+    // others can use is not rejected for having none
+    let ws_url = || match &cli.ws_url {
+        Some(ws_url) => Ok(ws_url.clone()),
+        None => watch::websocket_url(&cli.url),
+    };
+
+    match cli
+        .command
+        .unwrap_or_else(|| Command::Test(HarnessOptions::default()))
+    {
+        Command::Test(options) => {
+            harness::run(&cli.url, &ws_url()?, cli.commitment, program_id, &options).await
+        }
+        Command::Start {
+            buyer,
+            seller,
+            subscription_id,
+            validation_threshold,
+            send,
+        } => {
+            let buyer = read_keypair_file(&buyer)?;
+            let (pda, _) =
+                escrow::find_escrow_pda(&program_id, &buyer.pubkey(), &seller, &subscription_id);
+            let args = StartSubscriptionArgs {
+                subscription_id,
+                validation_threshold,
+            };
+            let instruction =
+                escrow::start_subscription(&program_id, &pda, &buyer.pubkey(), &seller, &args)?;
+            execute(
+                &client,
+                "start_subscription",
+                instruction,
+                &buyer,
+                &pda,
+                &send,
+            )
+        }
+        Command::Pay {
+            buyer,
+            seller,
+            subscription_id,
+            amount,
+            send,
+        } => {
+            let buyer = read_keypair_file(&buyer)?;
+            let (pda, _) =
+                escrow::find_escrow_pda(&program_id, &buyer.pubkey(), &seller, &subscription_id);
+            let args = MakePaymentArgs { amount };
+            let instruction =
+                escrow::make_payment(&program_id, &pda, &buyer.pubkey(), &seller, &args)?;
+            execute(&client, "make_payment", instruction, &buyer, &pda, &send)
+        }
+        Command::Cancel {
+            buyer,
+            seller,
+            subscription_id,
+            send,
+        } => {
+            let buyer = read_keypair_file(&buyer)?;
+            let (pda, _) =
+                escrow::find_escrow_pda(&program_id, &buyer.pubkey(), &seller, &subscription_id);
+            let instruction =
+                escrow::cancel_subscription(&program_id, &pda, &buyer.pubkey(), &seller);
+            execute(
+                &client,
+                "cancel_subscription",
+                instruction,
+                &buyer,
+                &pda,
+                &send,
+            )
+        }
+        Command::Withdraw {
+            seller,
+            buyer,
+            subscription_id,
+            validation_data,
+            send,
+        } => {
+            let seller = read_keypair_file(&seller)?;
+            let (pda, _) =
+                escrow::find_escrow_pda(&program_id, &buyer, &seller.pubkey(), &subscription_id);
+            let args = WithdrawFundsArgs { validation_data };
+            let instruction =
+                escrow::withdraw_funds(&program_id, &pda, &buyer, &seller.pubkey(), &args)?;
+            execute(&client, "withdraw_funds", instruction, &seller, &pda, &send)
+        }
+        Command::List {
+            buyer,
+            seller,
+            is_active,
+            csv,
+            json,
+        } => {
+            let filter = EscrowFilter {
+                buyer,
+                seller,
+                is_active,
+            };
+            let escrows = escrow::fetch_all_escrow_accounts(&client, &program_id, &filter)?;
+            if json {
+                print_escrow_json(&escrows)?;
+                return Ok(());
+            }
+            print_escrow_table(&escrows);
+            if let Some(path) = csv {
+                export_csv(&path, &escrows)?;
+                println!("Exported {} accounts to {}", escrows.len(), path.display());
+            }
+            Ok(())
+        }
+        Command::Decode { transaction } => {
+            let (message, account_keys) = match transaction.parse::<Signature>() {
+                Ok(signature) => {
+                    let confirmed = transaction::fetch_confirmed_transaction(&client, &signature)?;
+                    println!("Transaction {} (slot {})", signature, confirmed.slot);
+                    (confirmed.transaction.message, confirmed.account_keys)
+                }
+                Err(_) => {
+                    let transaction = transaction::decode_transaction(&transaction)?;
+                    let account_keys =
+                        transaction::resolve_account_keys(&client, &transaction.message)?;
+                    (transaction.message, account_keys)
+                }
+            };
+            let decoded = decoder::decode_message(&program_id, &message, &account_keys);
+            if decoded.is_empty() {
+                println!("No instructions for program {}", program_id);
+            }
+            for instruction in &decoded {
+                decoder::print_decoded_instruction(instruction);
+            }
+            Ok(())
+        }
+        Command::History { escrow } => {
+            let history = history::fetch_history(&client, &program_id, &escrow, None)?;
+            history::print_history(&escrow, &history);
+            Ok(())
+        }
+        Command::CheckIdl { idl } => {
+            let idl = idl::load(idl.as_deref())?;
+            println!(
+                "Checking client types against IDL {} {}",
+                idl.metadata.name, idl.metadata.version
+            );
+            idl::check_client_types(&idl)
+        }
+        Command::Index { db, command } => {
+            let mut indexer = Indexer::open(&db)?;
+            match command {
+                IndexCommand::Sync { escrow, follow } => {
+                    let address = escrow.unwrap_or(program_id);
+                    if follow {
+                        indexer.follow(&client, &ws_url()?, &program_id, &address)?;
+                    } else {
+                        let indexed = indexer.sync(&client, &program_id, &address)?;
+                        println!("Indexed {} instruction(s) into {}", indexed, db.display());
+                    }
+                }
+                IndexCommand::Open { seller } => {
+                    indexer::print_subscriptions(&indexer.open_subscriptions(seller.as_ref())?);
+                }
+                IndexCommand::Escrowed => println!(
+                    "{} SOL in escrow",
+                    format_sol_balance(indexer.total_escrowed()?)
+                ),
+                IndexCommand::Refunds => indexer::print_refunds(&indexer.refunds()?),
+            }
+            Ok(())
+        }
+        Command::Watch {
+            buyer,
+            seller,
+            subscription_id,
+            until_inactive,
+        } => {
+            let (pda, _) = escrow::find_escrow_pda(&program_id, &buyer, &seller, &subscription_id);
+            let watcher = EscrowWatcher::subscribe(
+                &ws_url()?,
+                &program_id,
+                client.commitment(),
+                &[
+                    (Role::Escrow, pda),
+                    (Role::Buyer, buyer),
+                    (Role::Seller, seller),
+                ],
+            )?;
+            println!("Watching escrow {} (Ctrl-C to stop)", pda);
+            if let Some(escrow_account) = escrow::fetch_escrow_account(&client, &pda)? {
+                escrow::print_escrow_account(&pda, &escrow_account);
+            }
+            while let Some(update) = watcher.next() {
+                update.print();
+                let inactive = update.role == Role::Escrow
+                    && update
+                        .escrow
+                        .as_ref()
+                        .is_none_or(|escrow_account| !escrow_account.is_active);
+                if until_inactive && inactive {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        Command::CreateNonceAccount { payer, authority } => {
+            let payer = read_keypair_file(&payer)?;
+            let authority = authority.unwrap_or_else(|| payer.pubkey());
+            let nonce_account =
+                nonce::create_nonce_account(&client, &payer, &Keypair::new(), &authority)?;
+            println!("✅ Nonce account created: {}", nonce_account);
+            println!("   Nonce: {}", nonce::fetch_nonce(&client, &nonce_account)?);
+            Ok(())
+        }
+        Command::Sign {
+            transaction,
+            keypair,
+        } => {
+            let keypair = read_keypair_file(&keypair)?;
+            let mut transaction = PartialTransaction::decode(&transaction)?;
+            let signature = transaction.sign(&keypair)?;
+            println!("{}", transaction.encode()?);
+            eprintln!("Signed by {}: {}", keypair.pubkey(), signature);
+            print_missing_signers(&transaction);
+            Ok(())
+        }
+        Command::Submit {
+            transaction,
+            simulate,
+            retry,
+        } => {
+            let transaction = PartialTransaction::decode(&transaction)?.into_transaction()?;
+            send_transaction(
+                &client,
+                "transaction",
+                &transaction,
+                &program_id,
+                simulate,
+                &retry.policy(),
+            )?;
+            Ok(())
+        }
+    }
+}
+
+/// Signs `instruction` with `signer` as fee payer, then either prints it
+/// (`--sign-only`) or sends it and prints the escrow account's post-state.
+fn execute(
+    client: &RpcClient,
+    label: &str,
+    instruction: Instruction,
+    signer: &Keypair,
+    pda: &Pubkey,
+    options: &SendOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let program_id = instruction.program_id;
+    let builder = options.transaction.builder(client)?;
+
+    if let Some(fee_payer) = options.fee_payer {
+        let message = match options.nonce {
+            Some(nonce_account) => {
+                let nonce = match options.nonce_blockhash {
+                    Some(nonce) => nonce,
+                    None => nonce::fetch_nonce(client, &nonce_account)?,
+                };
+                builder.nonce_message(
+                    &[instruction],
+                    &fee_payer,
+                    &nonce_account,
+                    &signer.pubkey(),
+                    nonce,
+                )?
+            }
+            None => builder.message(client, &[instruction], &fee_payer)?,
+        };
+        let mut transaction = PartialTransaction::new(message);
+        transaction.sign(signer)?;
+        println!("{}", transaction.encode()?);
+        print_missing_signers(&transaction);
+        return Ok(());
+    }
+
+    let policy = options.retry.policy();
+    match options.nonce {
+        Some(nonce_account) => {
+            let nonce = match options.nonce_blockhash {
+                Some(nonce) => nonce,
+                None => nonce::fetch_nonce(client, &nonce_account)?,
+            };
+            let transaction = builder.sign_with_nonce(
+                &[instruction],
+                &signer.pubkey(),
+                &[signer],
+                &nonce_account,
+                &signer.pubkey(),
+                nonce,
+            )?;
+
+            if options.sign_only {
+                println!("{}", transaction::encode_transaction(&transaction)?);
+                return Ok(());
+            }
+
+            send_transaction(
+                client,
+                label,
+                &transaction,
+                &program_id,
+                options.simulate,
+                &policy,
+            )?;
+        }
+        None => {
+            let instructions = [instruction];
+            if options.simulate {
+                let transaction =
+                    builder.build(client, &instructions, &signer.pubkey(), &[signer])?;
+                simulate_transaction(client, label, &transaction, &program_id)?;
+            }
+            // Signing against our own blockhash lets expired attempts be re-signed
+            let signature = submit::send_with_resign(client, &policy, |blockhash| {
+                builder.build_with_blockhash(&instructions, &signer.pubkey(), &[signer], blockhash)
+            })?;
+            print_confirmation(client, &signature)?;
+        }
+    }
+    print_post_state(client, pda)
+}
+
+fn print_missing_signers(transaction: &PartialTransaction) {
+    // Status goes to stderr so stdout can be piped into the next signer
+    for signer in transaction.missing_signers() {
+        eprintln!("Awaiting signature from {}", signer);
+    }
+}
+
+/// Sends an already signed transaction, which is rebroadcast but never re-signed.
+fn send_transaction(
+    client: &RpcClient,
+    label: &str,
+    transaction: &VersionedTransaction,
+    program_id: &Pubkey,
+    simulate: bool,
+    policy: &RetryPolicy,
+) -> Result<Signature, Box<dyn std::error::Error>> {
+    if simulate {
+        simulate_transaction(client, label, transaction, program_id)?;
+    }
+    let signature = submit::send_with_retry(client, transaction, policy)?;
+    print_confirmation(client, &signature)?;
+    Ok(signature)
+}
+
+fn simulate_transaction(
+    client: &RpcClient,
+    label: &str,
+    transaction: &VersionedTransaction,
+    program_id: &Pubkey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let record = simulation::simulate(client, label, transaction)?;
+    record.print(program_id);
+    match record.err {
+        Some(err) => Err(format!("Simulation failed: {}", err).into()),
+        None => Ok(()),
+    }
+}
+
+fn print_confirmation(
+    client: &RpcClient,
+    signature: &Signature,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("✅ Transaction confirmed. Signature: {}", signature);
+
+    let cost = transaction::fetch_transaction_cost(client, signature)?;
+    println!("   Fee: {} SOL", format_sol_balance(cost.fee));
+    if let Some(units) = cost.compute_units {
+        println!("   Compute units consumed: {}", units);
+    }
+    Ok(())
+}
+
+fn print_post_state(client: &RpcClient, pda: &Pubkey) -> Result<(), Box<dyn std::error::Error>> {
+    match escrow::fetch_escrow_account(client, pda)? {
+        Some(escrow_account) => escrow::print_escrow_account(pda, &escrow_account),
+        None => println!("Escrow account {} is closed", pda),
+    }
+    Ok(())
+}
+
+fn print_escrow_table(escrows: &[(Pubkey, EscrowAccount)]) {
+    println!(
+        "{:<44}  {:<20}  {:<44}  {:<44}  {:>8}  {:>14}  {:<6}",
+        "Escrow", "Subscription ID", "Buyer", "Seller", "Payments", "Total (SOL)", "Active"
+    );
+    for (pda, escrow_account) in escrows {
+        println!(
+            "{:<44}  {:<20}  {:<44}  {:<44}  {:>8}  {:>14}  {:<6}",
+            pda.to_string(),
+            escrow_account.subscription_id,
+            escrow_account.buyer.to_string(),
+            escrow_account.seller.to_string(),
+            escrow_account.payment_count,
+            format_sol_balance(escrow_account.total_amount),
+            escrow_account.is_active
+        );
+    }
+
+    let total_escrowed: u64 = escrows
+        .iter()
+        .map(|(_, escrow_account)| escrow_account.total_amount)
+        .sum();
+    println!(
+        "\n{} escrow accounts, {} SOL in total",
+        escrows.len(),
+        format_sol_balance(total_escrowed)
+    );
+}
+
+/// Escrow account in `list --json` output.
+#[derive(Serialize)]
+struct EscrowListing<'a> {
+    #[serde(with = "crate::json::pubkey")]
+    address: Pubkey,
+    #[serde(flatten)]
+    account: &'a EscrowAccount,
+}
+
+fn print_escrow_json(escrows: &[(Pubkey, EscrowAccount)]) -> serde_json::Result<()> {
+    let listings: Vec<_> = escrows
+        .iter()
+        .map(|(address, account)| EscrowListing {
+            address: *address,
+            account,
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&listings)?);
+    Ok(())
+}
+
+fn export_csv(
+    path: &Path,
+    escrows: &[(Pubkey, EscrowAccount)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = File::create(path)?;
+    writeln!(
+        file,
+        "escrow,subscription_id,buyer,seller,payment_count,total_amount,is_active,validation_threshold"
+    )?;
+    for (pda, escrow_account) in escrows {
+        writeln!(
+            file,
+            "{},\"{}\",{},{},{},{},{},{}",
+            pda,
+            escrow_account.subscription_id.replace('"', "\"\""),
+            escrow_account.buyer,
+            escrow_account.seller,
+            escrow_account.payment_count,
+            escrow_account.total_amount,
+            escrow_account.is_active,
+            escrow_account.validation_threshold
+        )?;
+    }
+    Ok(())
+}
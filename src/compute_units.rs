@@ -0,0 +1,220 @@
+use crate::{decoder, transaction::ConfirmedTransaction};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Compute units consumed per instruction, as stored in a baseline file.
+///
+/// The file holds one `<instruction> <units>` pair per line; blank lines and
+/// lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct ComputeUnitBaseline {
+    pub units: BTreeMap<String, u64>,
+}
+
+impl ComputeUnitBaseline {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut units = BTreeMap::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [instruction, value] => {
+                    units.insert(instruction.to_string(), value.parse()?);
+                }
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected `<instruction> <units>`",
+                        path.display(),
+                        number + 1
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(Self { units })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut contents = String::from("# Compute units consumed per escrow instruction\n");
+        for (instruction, units) in &self.units {
+            contents.push_str(&format!("{} {}\n", instruction, units));
+        }
+        fs::write(path, contents)
+    }
+
+    /// Describes every instruction whose measured usage exceeds its baseline by
+    /// more than `tolerance_percent`.
+    pub fn regressions(
+        &self,
+        measured: &BTreeMap<String, u64>,
+        tolerance_percent: f64,
+    ) -> Vec<String> {
+        let mut regressions = Vec::new();
+        for (instruction, &units) in measured {
+            let Some(&baseline) = self.units.get(instruction) else {
+                continue;
+            };
+            let limit = baseline as f64 * (1.0 + tolerance_percent / 100.0);
+            if units as f64 <= limit {
+                continue;
+            }
+            // Any usage regresses a zero baseline, by no meaningful percentage
+            let increase = if baseline == 0 {
+                String::new()
+            } else {
+                format!("+{:.1}%, ", (units as f64 / baseline as f64 - 1.0) * 100.0)
+            };
+            regressions.push(format!(
+                "{} consumed {} CU, baseline {} CU ({}tolerance {}%)",
+                instruction, units, baseline, increase, tolerance_percent
+            ));
+        }
+        regressions
+    }
+
+    /// Measured instructions the baseline has no entry for.
+    pub fn unbaselined<'a>(&self, measured: &'a BTreeMap<String, u64>) -> Vec<(&'a str, u64)> {
+        measured
+            .iter()
+            .filter(|(instruction, _)| !self.units.contains_key(*instruction))
+            .map(|(instruction, &units)| (instruction.as_str(), units))
+            .collect()
+    }
+}
+
+/// Compute units consumed by each top-level escrow instruction of a confirmed
+/// transaction, in order. Units include the instruction's CPIs but not the
+/// transaction's ComputeBudget or other instructions.
+pub fn instruction_units(
+    confirmed: &ConfirmedTransaction,
+    program_id: &Pubkey,
+) -> Result<Vec<(&'static str, u64)>, String> {
+    let names = confirmed
+        .transaction
+        .message
+        .instructions()
+        .iter()
+        .filter(|instruction| {
+            confirmed
+                .account_keys
+                .get(usize::from(instruction.program_id_index))
+                == Some(program_id)
+        })
+        .map(|instruction| decoder::decode_instruction_data(&instruction.data).map(|i| i.name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let logs: Option<Vec<String>> = confirmed.meta.log_messages.clone().into();
+    let units = top_level_units(&logs.unwrap_or_default(), program_id);
+    if units.len() != names.len() {
+        return Err(format!(
+            "Logs report compute units for {} of {} escrow instructions",
+            units.len(),
+            names.len()
+        ));
+    }
+    Ok(names.into_iter().zip(units).collect())
+}
+
+/// Units from the `Program <id> consumed <units> of <limit> compute units`
+/// log line of every top-level invocation of `program_id`.
+pub fn top_level_units(logs: &[String], program_id: &Pubkey) -> Vec<u64> {
+    let program = format!("Program {} ", program_id);
+    let mut depth = 0;
+    let mut units = Vec::new();
+    for line in logs {
+        // Only the runtime logs `Program <id> ...`; program output goes to
+        // `Program log: ...`, `Program data: ...` and the like
+        let Some((id, event)) = line
+            .strip_prefix("Program ")
+            .and_then(|rest| rest.split_once(' '))
+        else {
+            continue;
+        };
+        if id.parse::<Pubkey>().is_err() {
+            continue;
+        }
+        // Every invocation logs `invoke [depth]` and ends in success or failure
+        if let Some(invoke) = event.strip_prefix("invoke [") {
+            depth = invoke.trim_end_matches(']').parse().unwrap_or(depth);
+        } else if event == "success" || event.starts_with("failed: ") {
+            depth -= 1;
+        } else if let Some(consumed) = line
+            .strip_prefix(&program)
+            .and_then(|rest| rest.strip_prefix("consumed "))
+        {
+            if depth == 1 {
+                if let Some(Ok(value)) = consumed.split_whitespace().next().map(str::parse) {
+                    units.push(value);
+                }
+            }
+        }
+    }
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::system_program;
+
+    #[test]
+    fn reads_units_of_top_level_invocations() {
+        let program_id = Pubkey::new_from_array([3; 32]);
+        let system = system_program::id();
+        let budget = solana_sdk::compute_budget::id();
+        let logs: Vec<String> = [
+            format!("Program {} invoke [1]", budget),
+            format!("Program {} success", budget),
+            format!("Program {} invoke [1]", program_id),
+            "Program log: Instruction: MakePayment".to_string(),
+            format!("Program {} invoke [2]", system),
+            format!("Program {} success", system),
+            format!(
+                "Program {} consumed 6200 of 199850 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+            format!("Program {} invoke [1]", program_id),
+            "Program log: Instruction: MakePayment".to_string(),
+            format!(
+                "Program {} consumed 4100 of 193650 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+        ]
+        .into();
+        assert_eq!(top_level_units(&logs, &program_id), vec![6200, 4100]);
+    }
+
+    #[test]
+    fn ignores_program_output_resembling_runtime_lines() {
+        let program_id = Pubkey::new_from_array([3; 32]);
+        let logs: Vec<String> = [
+            format!("Program {} invoke [1]", program_id),
+            "Program log: validation failed: below threshold".to_string(),
+            "Program log: refund success".to_string(),
+            "Program log: invoke [2]".to_string(),
+            format!(
+                "Program {} consumed 5300 of 200000 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+        ]
+        .into();
+        assert_eq!(top_level_units(&logs, &program_id), vec![5300]);
+    }
+
+    #[test]
+    fn reports_regressions_over_a_zero_baseline() {
+        let baseline = ComputeUnitBaseline {
+            units: [("make_payment".to_string(), 0)].into(),
+        };
+        let measured = [("make_payment".to_string(), 100)].into();
+        let regressions = baseline.regressions(&measured, 10.0);
+        assert_eq!(
+            regressions,
+            vec!["make_payment consumed 100 CU, baseline 0 CU (tolerance 10%)"]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    balances::format_sol_balance,
+    escrow::{
+        MakePaymentArgs, StartSubscriptionArgs, WithdrawFundsArgs,
+        CANCEL_SUBSCRIPTION_DISCRIMINATOR, MAKE_PAYMENT_DISCRIMINATOR,
+        START_SUBSCRIPTION_DISCRIMINATOR, WITHDRAW_FUNDS_DISCRIMINATOR,
+    },
+};
+use anchor_lang::AnchorDeserialize;
+use solana_sdk::{message::VersionedMessage, pubkey::Pubkey};
+use std::fmt;
+
+/// Escrow program instruction recognised by its sighash, with decoded arguments.
+pub enum EscrowInstruction {
+    StartSubscription(StartSubscriptionArgs),
+    MakePayment(MakePaymentArgs),
+    CancelSubscription,
+    WithdrawFunds(WithdrawFundsArgs),
+}
+
+impl EscrowInstruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EscrowInstruction::StartSubscription(_) => "start_subscription",
+            EscrowInstruction::MakePayment(_) => "make_payment",
+            EscrowInstruction::CancelSubscription => "cancel_subscription",
+            EscrowInstruction::WithdrawFunds(_) => "withdraw_funds",
+        }
+    }
+}
+
+/// Inverse of the instruction builders in `escrow`: matches the first 8 bytes
+/// against the known sighashes and deserializes the remaining Borsh arguments.
+pub fn decode_instruction_data(data: &[u8]) -> Result<EscrowInstruction, String> {
+    if data.len() < 8 {
+        return Err(format!("Instruction data too short ({} bytes)", data.len()));
+    }
+    let (sighash, mut args) = data.split_at(8);
+    let instruction = if sighash == START_SUBSCRIPTION_DISCRIMINATOR {
+        StartSubscriptionArgs::deserialize(&mut args).map(EscrowInstruction::StartSubscription)
+    } else if sighash == MAKE_PAYMENT_DISCRIMINATOR {
+        MakePaymentArgs::deserialize(&mut args).map(EscrowInstruction::MakePayment)
+    } else if sighash == CANCEL_SUBSCRIPTION_DISCRIMINATOR {
+        Ok(EscrowInstruction::CancelSubscription)
+    } else if sighash == WITHDRAW_FUNDS_DISCRIMINATOR {
+        WithdrawFundsArgs::deserialize(&mut args).map(EscrowInstruction::WithdrawFunds)
+    } else {
+        return Err(format!("Unknown instruction sighash {:02x?}", sighash));
+    };
+    instruction.map_err(|e| format!("Invalid instruction arguments: {}", e))
+}
+
+/// Role of an account in an escrow instruction. Every instruction takes its
+/// accounts in this order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountRole {
+    Escrow,
+    Buyer,
+    Seller,
+    SystemProgram,
+}
+
+pub const ACCOUNT_ROLES: [AccountRole; 4] = [
+    AccountRole::Escrow,
+    AccountRole::Buyer,
+    AccountRole::Seller,
+    AccountRole::SystemProgram,
+];
+
+impl fmt::Display for AccountRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountRole::Escrow => write!(f, "escrow"),
+            AccountRole::Buyer => write!(f, "buyer"),
+            AccountRole::Seller => write!(f, "seller"),
+            AccountRole::SystemProgram => write!(f, "system_program"),
+        }
+    }
+}
+
+pub struct LabelledAccount {
+    /// `None` for accounts beyond those the program expects
+    pub role: Option<AccountRole>,
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+/// An instruction of a transaction that targets the escrow program.
+pub struct DecodedInstruction {
+    /// Position of the instruction within the transaction
+    pub index: usize,
+    pub instruction: Result<EscrowInstruction, String>,
+    pub accounts: Vec<LabelledAccount>,
+}
+
+impl DecodedInstruction {
+    pub fn account(&self, role: AccountRole) -> Option<&Pubkey> {
+        self.accounts
+            .iter()
+            .find(|account| account.role == Some(role))
+            .map(|account| &account.pubkey)
+    }
+}
+
+/// Decodes every instruction in `message` that targets `program_id`.
+///
+/// `account_keys` must include addresses loaded from lookup tables, as
+/// returned by `transaction::fetch_confirmed_transaction`; for unsigned or
+/// legacy transactions the static keys are enough.
+pub fn decode_message(
+    program_id: &Pubkey,
+    message: &VersionedMessage,
+    account_keys: &[Pubkey],
+) -> Vec<DecodedInstruction> {
+    message
+        .instructions()
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| {
+            account_keys.get(usize::from(instruction.program_id_index)) == Some(program_id)
+        })
+        .map(|(index, instruction)| {
+            let accounts = instruction
+                .accounts
+                .iter()
+                .enumerate()
+                .filter_map(|(position, &key_index)| {
+                    let key_index = usize::from(key_index);
+                    Some(LabelledAccount {
+                        role: ACCOUNT_ROLES.get(position).copied(),
+                        pubkey: *account_keys.get(key_index)?,
+                        is_signer: message.is_signer(key_index),
+                        is_writable: message.is_maybe_writable(key_index),
+                    })
+                })
+                .collect();
+            DecodedInstruction {
+                index,
+                instruction: decode_instruction_data(&instruction.data),
+                accounts,
+            }
+        })
+        .collect()
+}
+
+pub fn print_decoded_instruction(decoded: &DecodedInstruction) {
+    match &decoded.instruction {
+        Ok(instruction) => {
+            println!(
+                "\n=== Instruction {}: {} ===",
+                decoded.index,
+                instruction.name()
+            );
+            match instruction {
+                EscrowInstruction::StartSubscription(args) => {
+                    println!("Subscription ID: {}", args.subscription_id);
+                    println!("Validation threshold: {}", args.validation_threshold);
+                }
+                EscrowInstruction::MakePayment(args) => {
+                    println!("Amount: {} SOL", format_sol_balance(args.amount))
+                }
+                EscrowInstruction::CancelSubscription => {}
+                EscrowInstruction::WithdrawFunds(args) => {
+                    println!("Validation data: {}", args.validation_data)
+                }
+            }
+        }
+        Err(e) => println!(
+            "\n=== Instruction {}: undecodable ({}) ===",
+            decoded.index, e
+        ),
+    }
+    println!("Accounts:");
+    for account in &decoded.accounts {
+        let role = account
+            .role
+            .map_or_else(|| "extra".to_string(), |role| role.to_string());
+        let mut flags = Vec::new();
+        if account.is_writable {
+            flags.push("writable");
+        }
+        if account.is_signer {
+            flags.push("signer");
+        }
+        println!("  {:<14} {} {}", role, account.pubkey, flags.join(", "));
+    }
+    println!("========================");
+}
@@ -0,0 +1,241 @@
+use crate::{balances::format_sol_balance, layout::decode_escrow_account};
+use anchor_lang::{solana_program::hash::hash, AnchorDeserialize, AnchorSerialize, Discriminator};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+
+pub use solana_sdk::native_token::LAMPORTS_PER_SOL;
+pub const DEFAULT_VALIDATION_THRESHOLD: u64 = 1000;
+
+// Byte offsets of the fixed-position EscrowAccount fields, after the discriminator.
+const SELLER_OFFSET: usize = 8;
+const BUYER_OFFSET: usize = 40;
+
+// Argument and account structs, instruction builders and account fetchers,
+// generated from the program's IDL by build.rs.
+include!(concat!(env!("OUT_DIR"), "/escrow_generated.rs"));
+
+pub fn get_instruction_sighash(name: &str) -> [u8; 8] {
+    let preimage = format!("global:{}", name);
+    let hash = hash(preimage.as_bytes());
+    let mut sighash = [0u8; 8];
+    sighash.copy_from_slice(&hash.to_bytes()[..8]);
+    sighash
+}
+
+pub fn find_escrow_pda(
+    program_id: &Pubkey,
+    buyer: &Pubkey,
+    seller: &Pubkey,
+    subscription_id: &str,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"escrow",
+            buyer.as_ref(),
+            seller.as_ref(),
+            subscription_id.as_bytes(),
+        ],
+        program_id,
+    )
+}
+
+fn instruction_data<T: AnchorSerialize>(
+    discriminator: &[u8; 8],
+    args: &T,
+) -> std::io::Result<Vec<u8>> {
+    let args = args.try_to_vec()?;
+    let mut data = Vec::with_capacity(8 + args.len());
+    data.extend_from_slice(discriminator);
+    data.extend_from_slice(&args);
+    Ok(data)
+}
+
+/// Filters applied when enumerating escrow accounts.
+#[derive(Default)]
+pub struct EscrowFilter {
+    pub buyer: Option<Pubkey>,
+    pub seller: Option<Pubkey>,
+    pub is_active: Option<bool>,
+}
+
+/// Lists every escrow account owned by the program that matches `filter`.
+///
+/// Buyer and seller are matched server-side with memcmp filters. `is_active`
+/// sits behind the variable-length subscription ID, so it is checked after decoding.
+pub fn fetch_all_escrow_accounts(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    filter: &EscrowFilter,
+) -> Result<Vec<(Pubkey, EscrowAccount)>, Box<dyn std::error::Error>> {
+    let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        0,
+        &EscrowAccount::DISCRIMINATOR,
+    ))];
+    if let Some(seller) = filter.seller {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            SELLER_OFFSET,
+            seller.as_ref(),
+        )));
+    }
+    if let Some(buyer) = filter.buyer {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            BUYER_OFFSET,
+            buyer.as_ref(),
+        )));
+    }
+
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(client.commitment()),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let mut escrows = Vec::new();
+    for (pubkey, account) in client.get_program_accounts_with_config(program_id, config)? {
+        match decode_escrow_account(&account.data) {
+            Ok(decoded) => {
+                let escrow_account = decoded.account;
+                if filter
+                    .is_active
+                    .is_none_or(|is_active| escrow_account.is_active == is_active)
+                {
+                    escrows.push((pubkey, escrow_account));
+                }
+            }
+            Err(e) => eprintln!("Skipping undecodable escrow account {}: {}", pubkey, e),
+        }
+    }
+    Ok(escrows)
+}
+
+pub fn print_escrow_account(pda: &Pubkey, escrow: &EscrowAccount) {
+    println!("\n=== Escrow {} ===", pda);
+    println!("Subscription ID: {}", escrow.subscription_id);
+    println!("Seller: {}", escrow.seller);
+    println!("Buyer: {}", escrow.buyer);
+    println!("Payment count: {}", escrow.payment_count);
+    println!(
+        "Total amount: {} SOL",
+        format_sol_balance(escrow.total_amount)
+    );
+    println!("Is active: {}", escrow.is_active);
+    println!("Validation threshold: {}", escrow.validation_threshold);
+    println!("========================\n");
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        decoder,
+        layout::{parse_hex, tests::sample_escrow_account},
+    };
+    use anchor_lang::AccountSerialize;
+
+    const START_SUBSCRIPTION: &str =
+        include_str!("../fixtures/instruction_data/start_subscription.hex");
+    const MAKE_PAYMENT: &str = include_str!("../fixtures/instruction_data/make_payment.hex");
+    const CANCEL_SUBSCRIPTION: &str =
+        include_str!("../fixtures/instruction_data/cancel_subscription.hex");
+    const WITHDRAW_FUNDS: &str = include_str!("../fixtures/instruction_data/withdraw_funds.hex");
+
+    /// Program, escrow, buyer and seller of the golden instructions.
+    pub fn parties() -> (Pubkey, Pubkey, Pubkey, Pubkey) {
+        (
+            crate::ID,
+            Pubkey::new_from_array([3; 32]),
+            Pubkey::new_from_array([2; 32]),
+            Pubkey::new_from_array([1; 32]),
+        )
+    }
+
+    #[test]
+    fn start_subscription_matches_golden_file() {
+        let (program_id, escrow, buyer, seller) = parties();
+        let golden = parse_hex(START_SUBSCRIPTION).unwrap();
+        let args = StartSubscriptionArgs {
+            subscription_id: "premium_content".to_string(),
+            validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+        };
+        let instruction = start_subscription(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(instruction.data, golden);
+        let decoded = StartSubscriptionArgs::try_from_slice(&golden[8..]).unwrap();
+        assert_eq!(decoded.subscription_id, args.subscription_id);
+        assert_eq!(decoded.validation_threshold, args.validation_threshold);
+    }
+
+    #[test]
+    fn make_payment_matches_golden_file() {
+        let (program_id, escrow, buyer, seller) = parties();
+        let golden = parse_hex(MAKE_PAYMENT).unwrap();
+        let args = MakePaymentArgs {
+            amount: LAMPORTS_PER_SOL,
+        };
+        let instruction = make_payment(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(instruction.data, golden);
+        assert_eq!(
+            MakePaymentArgs::try_from_slice(&golden[8..])
+                .unwrap()
+                .amount,
+            args.amount
+        );
+    }
+
+    #[test]
+    fn cancel_subscription_matches_golden_file() {
+        let (program_id, escrow, buyer, seller) = parties();
+        let instruction = cancel_subscription(&program_id, &escrow, &buyer, &seller);
+        assert_eq!(instruction.data, parse_hex(CANCEL_SUBSCRIPTION).unwrap());
+    }
+
+    #[test]
+    fn withdraw_funds_matches_golden_file() {
+        let (program_id, escrow, buyer, seller) = parties();
+        let golden = parse_hex(WITHDRAW_FUNDS).unwrap();
+        let args = WithdrawFundsArgs {
+            validation_data: 42,
+        };
+        let instruction = withdraw_funds(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(instruction.data, golden);
+        assert_eq!(
+            WithdrawFundsArgs::try_from_slice(&golden[8..])
+                .unwrap()
+                .validation_data,
+            args.validation_data
+        );
+    }
+
+    #[test]
+    fn decoder_recognises_golden_instructions() {
+        for golden in [
+            START_SUBSCRIPTION,
+            MAKE_PAYMENT,
+            CANCEL_SUBSCRIPTION,
+            WITHDRAW_FUNDS,
+        ] {
+            decoder::decode_instruction_data(&parse_hex(golden).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn escrow_account_matches_golden_file() {
+        let mut data = Vec::new();
+        sample_escrow_account().try_serialize(&mut data).unwrap();
+        assert_eq!(
+            data,
+            parse_hex(include_str!("../fixtures/escrow_account/v1.hex")).unwrap()
+        );
+    }
+}
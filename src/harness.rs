@@ -0,0 +1,2611 @@
+use crate::{
+    balances::{self, Balance, BalanceSnapshot, BalanceTracker},
+    compute_units::{self, ComputeUnitBaseline},
+    decoder::{self, AccountRole, EscrowInstruction},
+    escrow::{
+        self, get_instruction_sighash, EscrowAccount, EscrowFilter, MakePaymentArgs,
+        StartSubscriptionArgs, WithdrawFundsArgs, DEFAULT_VALIDATION_THRESHOLD, LAMPORTS_PER_SOL,
+    },
+    history::{self, EscrowEvent, PaymentDestination, WithdrawalOutcome},
+    indexer::Indexer,
+    nonce,
+    program::{self, AnchorProgram, ClientPath},
+    replay::{self, Recording},
+    report::Report,
+    scenario::Scenario,
+    signing::PartialTransaction,
+    simulation::{self, SimulationRecord},
+    submit::{self, Commitment, RetryOptions, RetryPolicy},
+    transaction::{self, TransactionBuilder, TransactionOptions, TransactionVersion},
+    watch::{EscrowWatcher, Role},
+};
+use anchor_lang::AnchorSerialize;
+use clap::Args;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    hash::{hashv, Hash},
+    instruction::Instruction,
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    signature::{keypair_from_seed, Keypair, Signature, Signer},
+    system_program,
+    transaction::VersionedTransaction,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+mod expect;
+mod scenario;
+use expect::{expect, lamports_from_sol, EscrowState};
+
+const BUYER_INITIAL_BALANCE: u64 = 10 * LAMPORTS_PER_SOL;
+const SELLER_INITIAL_BALANCE: u64 = LAMPORTS_PER_SOL;
+const WATCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Args, Default)]
+pub struct HarnessOptions {
+    /// Run every transaction through simulateTransaction before sending it
+    #[arg(long)]
+    simulate_first: bool,
+
+    /// Write a report of the run, including captured simulations and balances,
+    /// to this file. Written as JSON if the file name ends in `.json`. With
+    /// several clients, each run's report is named after its client
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Compare compute units per instruction against this baseline file
+    #[arg(long)]
+    cu_baseline: Option<PathBuf>,
+
+    /// Allowed compute unit increase over the baseline, in percent
+    #[arg(long, default_value_t = 10.0)]
+    cu_tolerance: f64,
+
+    /// Overwrite the baseline file with this run's measurements instead of comparing
+    #[arg(long, requires = "cu_baseline")]
+    update_cu_baseline: bool,
+
+    /// Create an address lookup table with the program and system program
+    /// during setup and compile v0 transactions against it
+    #[arg(long)]
+    create_lookup_table: bool,
+
+    /// Clients used to send the start, payment, cancel and withdrawal flows and
+    /// read escrow accounts. The suite runs once with each, comparing the raw
+    /// encoding with anchor_client's. Defaults to `raw,anchor`, or `raw` alone
+    /// with --record, --replay or --simulate-first, which anchor_client can't
+    /// take part in
+    #[arg(long, value_enum, value_delimiter = ',')]
+    client: Vec<ClientPath>,
+
+    /// Only check the anchor_client encoding against the raw instruction
+    /// builders, and stop before setup. The IDL, golden file and other checks
+    /// that need no cluster run under `cargo test`
+    #[arg(long, conflicts_with = "cu_baseline")]
+    offline: bool,
+
+    /// Run the escrow flows described in this scenario file instead of the
+    /// built-in suite. Can be given more than once
+    #[arg(long = "scenario")]
+    scenarios: Vec<PathBuf>,
+
+    /// Record every RPC request and response of the run to this file, with
+    /// the seed its keypairs are derived from, random unless --seed is given
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve RPC responses from a file written by --record instead of the
+    /// cluster, deriving keypairs from the recording's seed. Websocket
+    /// notifications are not recorded, so watchers are skipped
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// After a recorded run passes, run it again against its recording, which
+    /// must see the same requests with the same parameters
+    #[arg(long, requires = "record")]
+    check_replay: bool,
+
+    /// Derive every keypair and subscription ID of the run from this seed,
+    /// so addresses and PDAs repeat between runs. Runs still go to the
+    /// cluster at --url: its blockhashes, clock and slots are not pinned, so
+    /// signatures and logs differ between runs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// After a seeded run passes, run the suite again with the same seed,
+    /// against the keypairs, PDAs and nonce account the first run left behind
+    #[arg(long, requires = "seed", conflicts_with_all = ["record", "replay"])]
+    check_rerun: bool,
+
+    #[command(flatten)]
+    transaction: TransactionOptions,
+
+    #[command(flatten)]
+    retry: RetryOptions,
+}
+
+impl HarnessOptions {
+    /// Clients to run the suite with, in order and without repeats.
+    fn clients(&self) -> Vec<ClientPath> {
+        let mut clients = Vec::new();
+        for &client in &self.client {
+            if !clients.contains(&client) {
+                clients.push(client);
+            }
+        }
+        if clients.is_empty() {
+            clients.push(ClientPath::Raw);
+            if self.record.is_none() && self.replay.is_none() && !self.simulate_first {
+                clients.push(ClientPath::Anchor);
+            }
+        }
+        clients
+    }
+
+    /// Where the run with `client` writes its report. With several clients
+    /// each gets its own, named after the client, so `report.json` becomes
+    /// `report.anchor.json`.
+    fn report_path(&self, client: ClientPath) -> Option<PathBuf> {
+        let path = self.report.as_ref()?;
+        if self.clients().len() == 1 {
+            return Some(path.clone());
+        }
+        let client = format!("{:?}", client).to_lowercase();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{}.{}.{}", stem, client, extension.to_string_lossy()),
+            None => format!("{}.{}", stem, client),
+        };
+        Some(path.with_file_name(name))
+    }
+}
+
+pub struct TestContext {
+    client: RpcClient,
+    ws_url: String,
+    program_id: Pubkey,
+    buyer: Keypair,
+    seller: Keypair,
+    simulate_first: bool,
+    transaction_builder: RefCell<TransactionBuilder>,
+    retry_policy: RetryPolicy,
+    client_path: ClientPath,
+    anchor: AnchorProgram,
+    create_lookup_table: bool,
+    report: RefCell<Report>,
+    fees: RefCell<HashMap<Signature, u64>>,
+    replaying: bool,
+    seed: Option<u64>,
+}
+
+/// The client a run sends its RPC requests through, and the seed its
+/// keypairs are derived from.
+struct Connection {
+    client: RpcClient,
+    seed: Option<u64>,
+    replaying: bool,
+}
+
+impl Connection {
+    /// Connects to the cluster, recording the traffic with `--record` or
+    /// serving it from a recording with `--replay`. Recorded runs are always
+    /// seeded, with a random seed unless `--seed` is given, and replays reuse
+    /// the recording's seed so they sign the same transactions.
+    fn open(
+        rpc_url: &str,
+        commitment: Commitment,
+        options: &HarnessOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(path) = &options.record {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            return Ok(Self {
+                client: replay::recording_client(rpc_url, path, commitment.config(), seed)?,
+                seed: Some(seed),
+                replaying: false,
+            });
+        }
+        if let Some(path) = &options.replay {
+            let recording = Recording::load(path)?;
+            if let Some(seed) = options.seed.filter(|&seed| seed != recording.seed) {
+                return Err(format!(
+                    "--seed {} differs from seed {} the recording was made with",
+                    seed, recording.seed
+                )
+                .into());
+            }
+            return Ok(Self::replay(recording, commitment, false));
+        }
+        Ok(Self {
+            client: RpcClient::new_with_commitment(rpc_url, commitment.config()),
+            seed: options.seed,
+            replaying: false,
+        })
+    }
+
+    fn replay(recording: Recording, commitment: Commitment, strict: bool) -> Self {
+        Self {
+            seed: Some(recording.seed),
+            client: recording.into_client(commitment.config(), strict),
+            replaying: true,
+        }
+    }
+}
+
+impl TestContext {
+    fn new(
+        connection: Connection,
+        rpc_url: &str,
+        ws_url: &str,
+        commitment: Commitment,
+        program_id: Pubkey,
+        client_path: ClientPath,
+        options: &HarnessOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let Connection {
+            client,
+            seed,
+            replaying,
+        } = connection;
+        let transaction_builder = options.transaction.builder(&client)?;
+        let anchor = AnchorProgram::new(rpc_url, ws_url, commitment.config(), program_id);
+
+        let buyer = new_keypair(seed, "buyer");
+        let seller = new_keypair(seed, "seller");
+
+        Ok(Self {
+            client,
+            ws_url: ws_url.to_string(),
+            program_id,
+            buyer,
+            seller,
+            simulate_first: options.simulate_first,
+            transaction_builder: RefCell::new(transaction_builder),
+            retry_policy: options.retry.policy(),
+            client_path,
+            anchor,
+            create_lookup_table: options.create_lookup_table,
+            report: RefCell::new(Report::default()),
+            fees: RefCell::new(HashMap::new()),
+            replaying,
+            seed,
+        })
+    }
+
+    /// A keypair for `name`, derived from the run's seed when there is one.
+    /// Names must be unique within a run.
+    fn keypair(&self, name: &str) -> Keypair {
+        new_keypair(self.seed, name)
+    }
+
+    /// The subscription ID for `name`, derived from the run's seed when there
+    /// is one and `name` itself otherwise. Names must be unique within a run.
+    fn subscription_id(&self, name: &str) -> String {
+        match self.seed {
+            Some(seed) => seeded_subscription_id(seed, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn find_subscription_pda(&self, subscription_id: &str) -> (Pubkey, u8) {
+        escrow::find_escrow_pda(
+            &self.program_id,
+            &self.buyer.pubkey(),
+            &self.seller.pubkey(),
+            subscription_id,
+        )
+    }
+
+    /// Subscribes to the escrow PDA and the context's buyer and seller.
+    /// There is nothing to subscribe to when replaying, where the recorded
+    /// responses already come in the order the notifications were waited for.
+    fn watch_subscription(
+        &self,
+        subscription_pda: &Pubkey,
+    ) -> Result<Option<EscrowWatcher>, Box<dyn std::error::Error>> {
+        if self.replaying {
+            return Ok(None);
+        }
+        EscrowWatcher::subscribe(
+            &self.ws_url,
+            &self.program_id,
+            self.client.commitment(),
+            &[
+                (Role::Escrow, *subscription_pda),
+                (Role::Buyer, self.buyer.pubkey()),
+                (Role::Seller, self.seller.pubkey()),
+            ],
+        )
+        .map(Some)
+    }
+
+    /// Tracks the balances of the context's seller and buyer and the escrow PDA.
+    fn balance_tracker(&self, subscription_pda: &Pubkey) -> BalanceTracker {
+        BalanceTracker::new([
+            ("seller", self.seller.pubkey()),
+            ("escrow", *subscription_pda),
+            ("buyer", self.buyer.pubkey()),
+        ])
+    }
+
+    /// Takes a snapshot with `tracker`, printing it and adding it to the report.
+    fn log_snapshot<'t>(
+        &self,
+        tracker: &'t mut BalanceTracker,
+        label: &str,
+    ) -> Result<&'t BalanceSnapshot, Box<dyn std::error::Error>> {
+        let snapshot = tracker.snapshot(&self.client, label)?;
+        snapshot.print();
+        self.report.borrow_mut().record_balances(snapshot);
+        Ok(snapshot)
+    }
+
+    /// Balances of the context's seller and buyer and the escrow PDA, which
+    /// are printed and added to the report when `log` is set.
+    async fn get_balances(
+        &self,
+        subscription_pda: &Pubkey,
+        label: &str,
+        log: bool,
+    ) -> Result<Balance, Box<dyn std::error::Error>> {
+        let mut tracker = self.balance_tracker(subscription_pda);
+        let snapshot = if log {
+            self.log_snapshot(&mut tracker, label)?
+        } else {
+            tracker.snapshot(&self.client, label)?
+        };
+
+        Ok(Balance {
+            seller: snapshot.lamports("seller").unwrap_or_default(),
+            escrow: snapshot.lamports("escrow").unwrap_or_default(),
+            buyer: snapshot.lamports("buyer").unwrap_or_default(),
+        })
+    }
+
+    async fn request_airdrop_with_confirmation(
+        &self,
+        pubkey: &Pubkey,
+        amount: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for attempt in 0..3 {
+            println!("Airdrop attempt {} for {}", attempt + 1, pubkey);
+
+            match self.client.request_airdrop(pubkey, amount) {
+                Ok(signature) => {
+                    // Wait for confirmation
+                    for _ in 0..32 {
+                        if self.client.confirm_transaction(&signature)? {
+                            // Verify the balance after confirmation
+                            let balance = self.get_balance(pubkey)?;
+                            if balance >= amount {
+                                println!(
+                                    "✅ Airdrop confirmed. Balance: {} SOL",
+                                    balances::format_sol_balance(balance)
+                                );
+                                return Ok(());
+                            }
+                        }
+                        std::thread::sleep(std::time::Duration::from_millis(500));
+                    }
+                }
+                Err(e) => {
+                    println!("Airdrop request failed: {}", e);
+                }
+            }
+
+            // Wait before retry
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+
+        Err("Failed to complete airdrop after multiple attempts".into())
+    }
+
+    async fn setup(&self) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Setting up test accounts...");
+
+        // Fund buyer
+        println!("\nFunding buyer account...");
+        self.request_airdrop_with_confirmation(&self.buyer.pubkey(), BUYER_INITIAL_BALANCE)
+            .await?;
+
+        // Fund seller
+        println!("\nFunding seller account...");
+        self.request_airdrop_with_confirmation(&self.seller.pubkey(), SELLER_INITIAL_BALANCE)
+            .await?;
+
+        // Final balance verification
+        let buyer_balance = self.get_balance(&self.buyer.pubkey())?;
+        let seller_balance = self.get_balance(&self.seller.pubkey())?;
+
+        println!("\nFinal balances:");
+        println!("Buyer: {} SOL", balances::format_sol_balance(buyer_balance));
+        println!(
+            "Seller: {} SOL",
+            balances::format_sol_balance(seller_balance)
+        );
+
+        if buyer_balance < LAMPORTS_PER_SOL || seller_balance < LAMPORTS_PER_SOL {
+            return Err("Failed to fund accounts with sufficient SOL".into());
+        }
+
+        if self.create_lookup_table {
+            println!("\nCreating address lookup table...");
+            let lookup_table = transaction::create_lookup_table(
+                &self.client,
+                &self.buyer,
+                vec![self.program_id, system_program::id()],
+            )?;
+            println!("✅ Lookup table created: {}", lookup_table.key);
+            self.transaction_builder
+                .replace_with(|builder| builder.clone().lookup_table(lookup_table));
+        }
+
+        Ok(())
+    }
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.client.get_balance(pubkey)?)
+    }
+
+    /// Fee charged to the payer of a transaction sent through `send_transaction`.
+    fn fee_paid(&self, signature: &Signature) -> u64 {
+        self.fees
+            .borrow()
+            .get(signature)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Signs `instructions` with `signer`, who also pays, and sends them,
+    /// re-signing against a fresh blockhash whenever the previous one expires
+    /// before confirmation. Recorded like `send_transaction`.
+    fn sign_and_send(
+        &self,
+        label: &str,
+        instructions: &[Instruction],
+        signer: &Keypair,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        let mut capture = None;
+        let result = submit::send_with_resign(&self.client, &self.retry_policy, |blockhash| {
+            let transaction = self.transaction_builder.borrow().build_with_blockhash(
+                instructions,
+                &signer.pubkey(),
+                &[signer],
+                blockhash,
+            )?;
+            self.simulate_before_send(label, &transaction, &mut capture)?;
+            Ok(transaction)
+        });
+        self.confirmed(result, capture)
+    }
+
+    /// Sends and confirms an already signed `transaction`, such as a durable
+    /// nonce or multi-party one, recording its fee and the compute units it
+    /// consumed under `label`. In simulate-first mode the transaction is
+    /// simulated beforehand and the capture is printed if either step fails.
+    fn send_transaction(
+        &self,
+        label: &str,
+        transaction: &VersionedTransaction,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        let mut capture = None;
+        let result = self
+            .simulate_before_send(label, transaction, &mut capture)
+            .and_then(|()| submit::send_with_retry(&self.client, transaction, &self.retry_policy));
+        self.confirmed(result, capture)
+    }
+
+    /// In simulate-first mode, simulates `transaction` into `capture` and
+    /// fails if the simulation did.
+    fn simulate_before_send(
+        &self,
+        label: &str,
+        transaction: &VersionedTransaction,
+        capture: &mut Option<SimulationRecord>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.simulate_first {
+            return Ok(());
+        }
+        let record = capture.insert(simulation::simulate(&self.client, label, transaction)?);
+        match &record.err {
+            Some(err) => Err(format!("Simulation of {} failed: {}", label, err).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports the simulation captured for a send, printing it if the send
+    /// failed, and records the cost of a confirmed one.
+    fn confirmed(
+        &self,
+        result: Result<Signature, Box<dyn std::error::Error>>,
+        capture: Option<SimulationRecord>,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        if let Some(record) = capture {
+            if result.is_err() {
+                record.print(&self.program_id);
+            }
+            self.report.borrow_mut().simulations.push(record);
+        }
+        let signature = result?;
+        self.record_cost(&signature)?;
+        Ok(signature)
+    }
+
+    /// Records the fee of a confirmed transaction and the compute units each
+    /// of its escrow instructions consumed.
+    fn record_cost(&self, signature: &Signature) -> Result<(), Box<dyn std::error::Error>> {
+        let confirmed = transaction::fetch_confirmed_transaction(&self.client, signature)?;
+        self.fees
+            .borrow_mut()
+            .insert(*signature, confirmed.meta.fee);
+        for (instruction, units) in compute_units::instruction_units(&confirmed, &self.program_id)?
+        {
+            self.report
+                .borrow_mut()
+                .record_compute_units(instruction, units);
+        }
+        Ok(())
+    }
+
+    /// Starts a subscription through the selected client path, paid for by the buyer.
+    async fn start_subscription(
+        &self,
+        subscription_pda: &Pubkey,
+        buyer: &Keypair,
+        seller: &Pubkey,
+        args: StartSubscriptionArgs,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        match self.client_path {
+            ClientPath::Raw => {
+                let instruction = escrow::start_subscription(
+                    &self.program_id,
+                    subscription_pda,
+                    &buyer.pubkey(),
+                    seller,
+                    &args,
+                )?;
+                self.sign_and_send("start_subscription", &[instruction], buyer)
+            }
+            ClientPath::Anchor => {
+                let signature = self
+                    .anchor
+                    .send(
+                        buyer,
+                        program::accounts::StartSubscription {
+                            escrow: *subscription_pda,
+                            buyer: buyer.pubkey(),
+                            seller: *seller,
+                            system_program: system_program::id(),
+                        },
+                        program::instruction::StartSubscription {
+                            subscription_id: args.subscription_id,
+                            validation_threshold: args.validation_threshold,
+                        },
+                    )
+                    .await?;
+                self.record_cost(&signature)?;
+                Ok(signature)
+            }
+        }
+    }
+
+    /// Makes a payment through the selected client path, paid for by the buyer.
+    async fn make_payment(
+        &self,
+        subscription_pda: &Pubkey,
+        buyer: &Keypair,
+        seller: &Pubkey,
+        args: MakePaymentArgs,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        match self.client_path {
+            ClientPath::Raw => {
+                let instruction = escrow::make_payment(
+                    &self.program_id,
+                    subscription_pda,
+                    &buyer.pubkey(),
+                    seller,
+                    &args,
+                )?;
+                self.sign_and_send("make_payment", &[instruction], buyer)
+            }
+            ClientPath::Anchor => {
+                let signature = self
+                    .anchor
+                    .send(
+                        buyer,
+                        program::accounts::MakePayment {
+                            escrow: *subscription_pda,
+                            buyer: buyer.pubkey(),
+                            seller: *seller,
+                            system_program: system_program::id(),
+                        },
+                        program::instruction::MakePayment {
+                            amount: args.amount,
+                        },
+                    )
+                    .await?;
+                self.record_cost(&signature)?;
+                Ok(signature)
+            }
+        }
+    }
+
+    /// Cancels a subscription through the selected client path, paid for by the buyer.
+    async fn cancel_subscription(
+        &self,
+        subscription_pda: &Pubkey,
+        buyer: &Keypair,
+        seller: &Pubkey,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        match self.client_path {
+            ClientPath::Raw => {
+                let instruction = escrow::cancel_subscription(
+                    &self.program_id,
+                    subscription_pda,
+                    &buyer.pubkey(),
+                    seller,
+                );
+                self.sign_and_send("cancel_subscription", &[instruction], buyer)
+            }
+            ClientPath::Anchor => {
+                let signature = self
+                    .anchor
+                    .send(
+                        buyer,
+                        program::accounts::CancelSubscription {
+                            escrow: *subscription_pda,
+                            buyer: buyer.pubkey(),
+                            seller: *seller,
+                            system_program: system_program::id(),
+                        },
+                        program::instruction::CancelSubscription {},
+                    )
+                    .await?;
+                self.record_cost(&signature)?;
+                Ok(signature)
+            }
+        }
+    }
+
+    /// Withdraws escrowed funds through the selected client path, paid for by the seller.
+    async fn withdraw_funds(
+        &self,
+        subscription_pda: &Pubkey,
+        seller: &Keypair,
+        buyer: &Pubkey,
+        args: WithdrawFundsArgs,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        match self.client_path {
+            ClientPath::Raw => {
+                let instruction = escrow::withdraw_funds(
+                    &self.program_id,
+                    subscription_pda,
+                    buyer,
+                    &seller.pubkey(),
+                    &args,
+                )?;
+                self.sign_and_send("withdraw_funds", &[instruction], seller)
+            }
+            ClientPath::Anchor => {
+                let signature = self
+                    .anchor
+                    .send(
+                        seller,
+                        program::accounts::WithdrawFunds {
+                            escrow: *subscription_pda,
+                            buyer: *buyer,
+                            seller: seller.pubkey(),
+                            system_program: system_program::id(),
+                        },
+                        program::instruction::WithdrawFunds {
+                            validation_data: args.validation_data,
+                        },
+                    )
+                    .await?;
+                self.record_cost(&signature)?;
+                Ok(signature)
+            }
+        }
+    }
+
+    /// Reads an escrow account through the selected client path.
+    async fn fetch_escrow_account(
+        &self,
+        subscription_pda: &Pubkey,
+    ) -> Result<EscrowAccount, Box<dyn std::error::Error>> {
+        match self.client_path {
+            ClientPath::Raw => escrow::fetch_escrow_account(&self.client, subscription_pda)?
+                .ok_or_else(|| format!("Escrow account {} is closed", subscription_pda).into()),
+            ClientPath::Anchor => self.anchor.escrow_account(subscription_pda).await,
+        }
+    }
+
+    /// Cancels an escrow a test leaves open and withdraws with validation data
+    /// over its threshold, refunding the buyer and closing the account, so a
+    /// rerun with the same `--seed` can start it again.
+    async fn close_escrow(
+        &self,
+        subscription_pda: &Pubkey,
+        buyer: &Keypair,
+        seller: &Keypair,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let escrow_account = self.fetch_escrow_account(subscription_pda).await?;
+        if escrow_account.is_active {
+            self.cancel_subscription(subscription_pda, buyer, &seller.pubkey())
+                .await?;
+        }
+        let args = WithdrawFundsArgs {
+            validation_data: escrow_account.validation_threshold + 1,
+        };
+        self.withdraw_funds(subscription_pda, seller, &buyer.pubkey(), args)
+            .await?;
+        expect(self)
+            .escrow(subscription_pda)
+            .closed()
+            .holds()
+            .await?;
+        println!("✅ Escrow {} closed", subscription_pda);
+        Ok(())
+    }
+
+    /// Compares this run's compute units against the baseline file, or rewrites
+    /// it when `--update-cu-baseline` is set.
+    fn check_compute_units(
+        &self,
+        options: &HarnessOptions,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &options.cu_baseline else {
+            return Ok(());
+        };
+        let measured = self.report.borrow().compute_units.clone();
+
+        if options.update_cu_baseline {
+            ComputeUnitBaseline { units: measured }.save(path)?;
+            println!("Compute unit baseline written to {}", path.display());
+            return Ok(());
+        }
+
+        let baseline = ComputeUnitBaseline::load(path)?;
+        for (instruction, units) in baseline.unbaselined(&measured) {
+            println!(
+                "No compute unit baseline for {} ({} CU)",
+                instruction, units
+            );
+        }
+        let regressions = baseline.regressions(&measured, options.cu_tolerance);
+        if !regressions.is_empty() {
+            for regression in &regressions {
+                println!("❌ {}", regression);
+            }
+            return Err(format!(
+                "{} instruction(s) exceeded their compute unit baseline",
+                regressions.len()
+            )
+            .into());
+        }
+
+        println!(
+            "\n✅ Compute units within {}% of baseline",
+            options.cu_tolerance
+        );
+        Ok(())
+    }
+}
+
+/// A subscription ID derived from `seed` and `name`. IDs are PDA seeds, so
+/// they are kept well under the 32 byte limit.
+fn seeded_subscription_id(seed: u64, name: &str) -> String {
+    let hash = hashv(&[
+        b"escrow-harness/subscription",
+        &seed.to_le_bytes(),
+        name.as_bytes(),
+    ]);
+    let hex: String = hash.as_ref()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sub-{}", hex)
+}
+
+/// A new keypair, or with a seed, the one derived from it and `name`.
+fn new_keypair(seed: Option<u64>, name: &str) -> Keypair {
+    match seed {
+        Some(seed) => {
+            let secret = hashv(&[b"escrow-harness", &seed.to_le_bytes(), name.as_bytes()]);
+            keypair_from_seed(secret.as_ref()).expect("hash is a valid keypair seed")
+        }
+        None => Keypair::new(),
+    }
+}
+
+pub async fn run(
+    rpc_url: &str,
+    ws_url: &str,
+    commitment: Commitment,
+    program_id: Pubkey,
+    options: &HarnessOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Load scenarios first, so a mistake in one is reported before any setup
+    let scenarios = options
+        .scenarios
+        .iter()
+        .map(|path| Scenario::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let clients = options.clients();
+    // anchor_client opens its own connection, which cannot be recorded or replayed
+    if (options.record.is_some() || options.replay.is_some())
+        && clients.contains(&ClientPath::Anchor)
+    {
+        return Err("--record and --replay require --client raw".into());
+    }
+    // anchor_client sends its own transactions, which cannot be simulated first
+    if options.simulate_first && clients.contains(&ClientPath::Anchor) {
+        return Err("--simulate-first requires --client raw".into());
+    }
+
+    for (number, &client) in clients.iter().enumerate() {
+        if clients.len() > 1 {
+            println!(
+                "\n=== Client {} of {}: {:?} ===",
+                number + 1,
+                clients.len(),
+                client
+            );
+        }
+        run_client(
+            rpc_url, ws_url, commitment, program_id, client, options, &scenarios,
+        )
+        .await?;
+        // The offline checks don't depend on the client
+        if options.offline {
+            break;
+        }
+    }
+    if clients.len() > 1 && !options.offline {
+        println!("\n✅ Suite passed with clients {:?}", clients);
+    }
+    Ok(())
+}
+
+/// One run of the suite with `client`.
+async fn run_client(
+    rpc_url: &str,
+    ws_url: &str,
+    commitment: Commitment,
+    program_id: Pubkey,
+    client: ClientPath,
+    options: &HarnessOptions,
+    scenarios: &[Scenario],
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Initializing test environment...");
+    let connection = Connection::open(rpc_url, commitment, options)?;
+    let context = TestContext::new(
+        connection, rpc_url, ws_url, commitment, program_id, client, options,
+    )?;
+    if context.create_lookup_table
+        && context.transaction_builder.borrow().version() != TransactionVersion::V0
+    {
+        return Err("--create-lookup-table requires --tx-version v0".into());
+    }
+    println!(
+        "Transaction version: {:?}",
+        context.transaction_builder.borrow().version()
+    );
+    println!("Client: {:?}", context.client_path);
+    if let Some(path) = &options.record {
+        println!("Recording RPC traffic to {}", path.display());
+    }
+    if let Some(path) = &options.replay {
+        println!("Replaying RPC traffic from {}", path.display());
+    }
+    if let Some(seed) = context.seed {
+        println!("Seed: {}", seed);
+    }
+
+    if options.offline {
+        return run_offline_tests(&context).await;
+    }
+
+    let outcome = match run_suite(&context, scenarios).await {
+        Ok(()) => context.check_compute_units(options),
+        Err(e) => Err(e),
+    };
+    let outcome = match (outcome, &options.record) {
+        (Ok(()), Some(path)) if options.check_replay => {
+            check_replay(path, ws_url, commitment, program_id, options, scenarios).await
+        }
+        (outcome, _) => outcome,
+    };
+    let outcome = match outcome {
+        Ok(()) if options.check_rerun => {
+            check_rerun(
+                rpc_url, ws_url, commitment, program_id, client, options, scenarios,
+            )
+            .await
+        }
+        outcome => outcome,
+    };
+
+    if let Some(path) = options.report_path(client) {
+        context
+            .report
+            .borrow()
+            .write(&path, &context.program_id, &outcome)?;
+        println!("Report written to {}", path.display());
+    }
+
+    outcome
+}
+
+/// The built-in suite, or the given scenarios instead.
+async fn run_suite(
+    context: &TestContext,
+    scenarios: &[Scenario],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if scenarios.is_empty() {
+        run_tests(context).await
+    } else {
+        run_scenarios(context, scenarios).await
+    }
+}
+
+/// Runs the suite again against the recording a run just made. The replay
+/// must make every recorded request, with the recorded parameters.
+async fn check_replay(
+    path: &Path,
+    ws_url: &str,
+    commitment: Commitment,
+    program_id: Pubkey,
+    options: &HarnessOptions,
+    scenarios: &[Scenario],
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\n=== Replaying {} ===", path.display());
+    let recording = Recording::load(path)?;
+    let recorded = recording.len();
+    let connection = Connection::replay(recording, commitment, true);
+    let replay_url = connection.client.url();
+    let context = TestContext::new(
+        connection,
+        &replay_url,
+        ws_url,
+        commitment,
+        program_id,
+        ClientPath::Raw,
+        options,
+    )?;
+    run_suite(&context, scenarios)
+        .await
+        .map_err(|e| format!("Replay of {} failed: {}", path.display(), e))?;
+
+    let replayed = context.client.get_transport_stats().request_count;
+    if replayed != recorded {
+        return Err(format!(
+            "Replay made {} of the {} recorded requests",
+            replayed, recorded
+        )
+        .into());
+    }
+    println!("✅ Replay reproduced all {} recorded requests", recorded);
+    Ok(())
+}
+
+/// Runs the suite a second time with the run's seed. Everything the first run
+/// left on the cluster under the seeded addresses must not change the outcome.
+async fn check_rerun(
+    rpc_url: &str,
+    ws_url: &str,
+    commitment: Commitment,
+    program_id: Pubkey,
+    client: ClientPath,
+    options: &HarnessOptions,
+    scenarios: &[Scenario],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let seed = options.seed.ok_or("--check-rerun requires --seed")?;
+    println!("\n=== Rerunning with seed {} ===", seed);
+    let connection = Connection::open(rpc_url, commitment, options)?;
+    let context = TestContext::new(
+        connection, rpc_url, ws_url, commitment, program_id, client, options,
+    )?;
+    run_suite(&context, scenarios)
+        .await
+        .map_err(|e| format!("Rerun with seed {} failed: {}", seed, e))?;
+    println!("✅ Rerun with seed {} passed", seed);
+    Ok(())
+}
+
+/// Tests that need no cluster, run first so a client out of sync with the
+/// program fails fast.
+async fn run_offline_tests(context: &TestContext) -> Result<(), Box<dyn std::error::Error>> {
+    test_anchor_encoding_matches_raw(context).await?;
+    Ok(())
+}
+
+async fn run_scenarios(
+    context: &TestContext,
+    scenarios: &[Scenario],
+) -> Result<(), Box<dyn std::error::Error>> {
+    run_offline_tests(context).await?;
+
+    println!("Setting up accounts...");
+    context.setup().await?;
+
+    for scenario in scenarios {
+        scenario::run_scenario(context, scenario).await?;
+    }
+    println!("\n✅ All {} scenarios passed", scenarios.len());
+    Ok(())
+}
+
+async fn run_tests(context: &TestContext) -> Result<(), Box<dyn std::error::Error>> {
+    run_offline_tests(context).await?;
+
+    println!("Setting up accounts...");
+    context.setup().await?;
+
+    // Create subscription ID and PDA
+    let subscription_id = context.subscription_id("premium_content");
+    let (subscription_pda, _) = context.find_subscription_pda(&subscription_id);
+
+    println!("\nInitial setup");
+    println!("Subscription PDA: {}", subscription_pda);
+    context
+        .get_balances(&subscription_pda, "INITIAL SETUP", true)
+        .await?;
+
+    // A seeded run reuses the PDA of an earlier run with the same seed, so
+    // history and indexing only look at what this run sends
+    let history_start = history::latest_signature(&context.client, &subscription_pda)?;
+
+    // Run all tests
+    test_start_subscription(context, &subscription_id).await?;
+    test_list_escrow_accounts(context, &subscription_id).await?;
+    test_decode_instructions(context, &subscription_id).await?;
+    test_make_first_five_payments(context, &subscription_id).await?;
+    test_make_direct_payments(context, &subscription_id).await?;
+    test_cancel_subscription(context, &subscription_id).await?;
+    test_failed_withdrawal(context, &subscription_id).await?;
+    test_subscription_history(context, &subscription_id, history_start).await?;
+    test_indexer(context, &subscription_id, history_start).await?;
+    test_successful_withdrawal(context).await?;
+    test_durable_nonce_payments(context).await?;
+    test_sponsored_withdrawal(context).await?;
+    test_batched_payments(context).await?;
+    test_lookup_table_payment(context).await?;
+    test_resubmission(context).await?;
+
+    Ok(())
+}
+
+async fn test_anchor_encoding_matches_raw(
+    context: &TestContext,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting anchor_client encoding against the raw instruction builders...");
+    let (subscription_pda, _) = context.find_subscription_pda("anchor_encoding");
+    let buyer = context.buyer.pubkey();
+    let seller = context.seller.pubkey();
+
+    let start_args = StartSubscriptionArgs {
+        subscription_id: "anchor_encoding".to_string(),
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let raw = escrow::start_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &buyer,
+        &seller,
+        &start_args,
+    )?;
+    let anchor = context.anchor.instruction(
+        program::accounts::StartSubscription {
+            escrow: subscription_pda,
+            buyer,
+            seller,
+            system_program: system_program::id(),
+        },
+        program::instruction::StartSubscription {
+            subscription_id: start_args.subscription_id.clone(),
+            validation_threshold: start_args.validation_threshold,
+        },
+    )?;
+    assert_eq!(raw, anchor, "start_subscription encodings differ");
+
+    let raw = escrow::make_payment(
+        &context.program_id,
+        &subscription_pda,
+        &buyer,
+        &seller,
+        &MakePaymentArgs {
+            amount: LAMPORTS_PER_SOL,
+        },
+    )?;
+    let anchor = context.anchor.instruction(
+        program::accounts::MakePayment {
+            escrow: subscription_pda,
+            buyer,
+            seller,
+            system_program: system_program::id(),
+        },
+        program::instruction::MakePayment {
+            amount: LAMPORTS_PER_SOL,
+        },
+    )?;
+    assert_eq!(raw, anchor, "make_payment encodings differ");
+
+    let raw = escrow::cancel_subscription(&context.program_id, &subscription_pda, &buyer, &seller);
+    let anchor = context.anchor.instruction(
+        program::accounts::CancelSubscription {
+            escrow: subscription_pda,
+            buyer,
+            seller,
+            system_program: system_program::id(),
+        },
+        program::instruction::CancelSubscription {},
+    )?;
+    assert_eq!(raw, anchor, "cancel_subscription encodings differ");
+
+    let raw = escrow::withdraw_funds(
+        &context.program_id,
+        &subscription_pda,
+        &buyer,
+        &seller,
+        &WithdrawFundsArgs {
+            validation_data: 42,
+        },
+    )?;
+    let anchor = context.anchor.instruction(
+        program::accounts::WithdrawFunds {
+            escrow: subscription_pda,
+            buyer,
+            seller,
+            system_program: system_program::id(),
+        },
+        program::instruction::WithdrawFunds {
+            validation_data: 42,
+        },
+    )?;
+    assert_eq!(raw, anchor, "withdraw_funds encodings differ");
+
+    println!("✅ anchor_client and raw instructions are identical\n");
+
+    Ok(())
+}
+
+async fn test_start_subscription(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Start Subscription...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    // let pre_balances = context
+    //     .get_balances(&subscription_pda, "BEFORE SUBSCRIPTION START", true)
+    //     .await?;
+
+    // Create instruction
+    let args = StartSubscriptionArgs {
+        subscription_id: subscription_id.to_string(),
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let signature = context
+        .start_subscription(
+            &subscription_pda,
+            &context.buyer,
+            &context.seller.pubkey(),
+            args,
+        )
+        .await?;
+    println!("✅ Subscription started. Signature: {}", signature);
+
+    // let post_balances = context
+    //     .get_balances(&subscription_pda, "AFTER SUBSCRIPTION START", true)
+    //     .await?;
+
+    // Verify account data
+    expect(context)
+        .escrow(&subscription_pda)
+        .seller(&context.seller.pubkey())
+        .buyer(&context.buyer.pubkey())
+        .subscription_id(subscription_id)
+        .payment_count(0)
+        .is_active(true)
+        .holds()
+        .await?;
+
+    Ok(())
+}
+
+async fn test_list_escrow_accounts(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting List Escrow Accounts...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    let filter = EscrowFilter {
+        buyer: Some(context.buyer.pubkey()),
+        seller: Some(context.seller.pubkey()),
+        is_active: Some(true),
+    };
+    let escrows = escrow::fetch_all_escrow_accounts(&context.client, &context.program_id, &filter)?;
+
+    assert_eq!(escrows.len(), 1, "Expected exactly one matching escrow");
+    assert_eq!(escrows[0].0, subscription_pda);
+    assert_eq!(escrows[0].1.subscription_id, subscription_id);
+
+    // The same subscription must be excluded once we ask for inactive ones
+    let filter = EscrowFilter {
+        is_active: Some(false),
+        ..filter
+    };
+    let escrows = escrow::fetch_all_escrow_accounts(&context.client, &context.program_id, &filter)?;
+    assert!(
+        escrows.is_empty(),
+        "Active subscription should not match is_active=false"
+    );
+
+    println!("✅ Escrow account listed. PDA: {}", subscription_pda);
+
+    Ok(())
+}
+
+async fn test_decode_instructions(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Instruction Decoder...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+    let buyer = context.buyer.pubkey();
+    let seller = context.seller.pubkey();
+
+    let instructions = [
+        escrow::make_payment(
+            &context.program_id,
+            &subscription_pda,
+            &buyer,
+            &seller,
+            &MakePaymentArgs {
+                amount: LAMPORTS_PER_SOL,
+            },
+        )?,
+        escrow::withdraw_funds(
+            &context.program_id,
+            &subscription_pda,
+            &buyer,
+            &seller,
+            &WithdrawFundsArgs {
+                validation_data: 42,
+            },
+        )?,
+    ];
+    // Never sent; the buyer pays so both parties sign
+    let transaction = context.transaction_builder.borrow().build(
+        &context.client,
+        &instructions,
+        &buyer,
+        &[&context.buyer, &context.seller],
+    )?;
+    let account_keys = transaction::resolve_account_keys(&context.client, &transaction.message)?;
+    let decoded = decoder::decode_message(&context.program_id, &transaction.message, &account_keys);
+
+    // ComputeBudget instructions, if any, belong to another program
+    assert_eq!(decoded.len(), 2, "Expected 2 escrow instructions");
+    for instruction in &decoded {
+        decoder::print_decoded_instruction(instruction);
+        assert_eq!(
+            instruction.account(AccountRole::Escrow),
+            Some(&subscription_pda)
+        );
+        assert_eq!(instruction.account(AccountRole::Buyer), Some(&buyer));
+        assert_eq!(instruction.account(AccountRole::Seller), Some(&seller));
+        assert_eq!(
+            instruction.account(AccountRole::SystemProgram),
+            Some(&system_program::id())
+        );
+    }
+    match &decoded[0].instruction {
+        Ok(EscrowInstruction::MakePayment(args)) => assert_eq!(args.amount, LAMPORTS_PER_SOL),
+        _ => return Err("First instruction should decode as make_payment".into()),
+    }
+    match &decoded[1].instruction {
+        Ok(EscrowInstruction::WithdrawFunds(args)) => assert_eq!(args.validation_data, 42),
+        _ => return Err("Second instruction should decode as withdraw_funds".into()),
+    }
+
+    let seller_account = &decoded[1].accounts[2];
+    assert!(seller_account.is_signer && seller_account.is_writable);
+    assert!(decoded[1].index > decoded[0].index);
+
+    assert!(
+        decoder::decode_instruction_data(&get_instruction_sighash("unknown")).is_err(),
+        "Unknown sighash should not decode"
+    );
+
+    println!("✅ Escrow instructions decoded");
+
+    Ok(())
+}
+
+async fn test_make_first_five_payments(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+    let payment_amount = LAMPORTS_PER_SOL; // 1 SOL
+    let watcher = context.watch_subscription(&subscription_pda)?;
+    let mut tracker = context.balance_tracker(&subscription_pda);
+
+    for i in 0..5 {
+        println!("\nMaking payment {} of 5...", i + 1);
+
+        // Get balances before payment
+        tracker.snapshot(&context.client, &format!("BEFORE PAYMENT {}", i + 1))?;
+
+        // Create payment instruction
+        let args = MakePaymentArgs {
+            amount: payment_amount,
+        };
+        let signature = context
+            .make_payment(
+                &subscription_pda,
+                &context.buyer,
+                &context.seller.pubkey(),
+                args,
+            )
+            .await?;
+        let post_balances =
+            context.log_snapshot(&mut tracker, &format!("AFTER PAYMENT {}", i + 1))?;
+        let seller_balance = post_balances.lamports("seller").unwrap_or_default();
+
+        // The escrow holds the payment; the buyer pays the fee, so the
+        // seller's balance doesn't change
+        tracker.expect_delta("escrow", payment_amount as i64)?;
+        tracker.expect_delta("seller", 0)?;
+
+        println!(
+            "✅ Payment {} successful. Signature: {}\n   Amount: {} SOL\n   Escrow increase: {} SOL\n   Seller balance unchanged: {} SOL",
+            i + 1,
+            signature,
+            balances::format_sol_balance(payment_amount),
+            balances::format_sol(payment_amount as i64),
+            balances::format_sol_balance(seller_balance)
+        );
+
+        // Wait for the websocket to report this payment before the next one
+        if let Some(watcher) = &watcher {
+            watcher.wait_for_escrow(WATCH_TIMEOUT, |escrow_account| {
+                escrow_account.payment_count == i + 1
+            })?;
+        }
+    }
+
+    // Final verification of escrow account data
+    expect(context)
+        .escrow(&subscription_pda)
+        .payment_count(5)
+        .total_amount(payment_amount * 5)
+        .holds()
+        .await?;
+
+    println!("\n✅ All 5 payments completed and verified successfully!");
+    println!(
+        "   Total in escrow: {} SOL",
+        balances::format_sol_balance(payment_amount * 5)
+    );
+
+    Ok(())
+}
+
+async fn test_make_direct_payments(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+    let payment_amount = LAMPORTS_PER_SOL; // 1 SOL
+    let watcher = context.watch_subscription(&subscription_pda)?;
+    let mut tracker = context.balance_tracker(&subscription_pda);
+
+    for i in 5..7 {
+        println!("\nMaking direct payment {} ...", i + 1);
+        tracker.snapshot(&context.client, &format!("BEFORE DIRECT PAYMENT {}", i + 1))?;
+
+        // Create payment instruction
+        let args = MakePaymentArgs {
+            amount: payment_amount,
+        };
+        let signature = context
+            .make_payment(
+                &subscription_pda,
+                &context.buyer,
+                &context.seller.pubkey(),
+                args,
+            )
+            .await?;
+        let post_balances =
+            context.log_snapshot(&mut tracker, &format!("AFTER DIRECT PAYMENT {}", i + 1))?;
+        let escrow_balance = post_balances.lamports("escrow").unwrap_or_default();
+
+        // The seller receives the payment directly and the escrow is untouched
+        tracker.expect_delta("seller", payment_amount as i64)?;
+        tracker.expect_delta("escrow", 0)?;
+
+        println!(
+            "✅ Direct payment {} successful. Signature: {}\n   Amount: {} SOL\n   Seller increase: {} SOL\n   Escrow unchanged: {} SOL",
+            i + 1,
+            signature,
+            balances::format_sol_balance(payment_amount),
+            balances::format_sol(payment_amount as i64),
+            balances::format_sol_balance(escrow_balance)
+        );
+
+        // Wait for the websocket to report this payment before the next one
+        if let Some(watcher) = &watcher {
+            watcher.wait_for_escrow(WATCH_TIMEOUT, |escrow_account| {
+                escrow_account.payment_count == i + 1
+            })?;
+        }
+    }
+
+    // Final verification of payment count
+    expect(context)
+        .escrow(&subscription_pda)
+        .payment_count(7)
+        .holds()
+        .await?;
+
+    println!("\n✅ Both direct payments completed successfully!");
+    Ok(())
+}
+
+async fn test_cancel_subscription(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Cancel Subscription...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    let mut tracker = context.balance_tracker(&subscription_pda);
+    context.log_snapshot(&mut tracker, "BEFORE CANCELLATION")?;
+    let before = EscrowState::fetch(context, &subscription_pda).await?;
+    let watcher = context.watch_subscription(&subscription_pda)?;
+
+    let signature = context
+        .cancel_subscription(&subscription_pda, &context.buyer, &context.seller.pubkey())
+        .await?;
+    println!("✅ Cancel transaction confirmed. Signature: {}", signature);
+
+    if let Some(watcher) = &watcher {
+        let update = watcher.wait_for(WATCH_TIMEOUT, |update| {
+            update.role == Role::Escrow
+                && update
+                    .escrow
+                    .as_ref()
+                    .is_some_and(|escrow_account| !escrow_account.is_active)
+        })?;
+        println!(
+            "✅ Cancellation observed over websocket at slot {}",
+            update.slot
+        );
+    }
+
+    context.log_snapshot(&mut tracker, "AFTER CANCELLATION")?;
+
+    // Verify the subscription is inactive and the escrow kept its funds
+    let after = expect(context)
+        .escrow(&subscription_pda)
+        .since(&before)
+        .is_active(false)
+        .lamports_delta(0)
+        .holds()
+        .await?;
+    tracker.expect_delta("seller", 0)?;
+
+    println!("\n✅ Subscription cancelled successfully!");
+    println!(
+        "   Escrow balance: {} SOL",
+        balances::format_sol_balance(after.lamports)
+    );
+    println!("   Is active: false");
+
+    Ok(())
+}
+
+async fn test_failed_withdrawal(
+    context: &TestContext,
+    subscription_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Failed Withdrawal (Scammer Scenario)...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    let mut tracker = context.balance_tracker(&subscription_pda);
+    let pre_balances = context.log_snapshot(&mut tracker, "BEFORE FAILED WITHDRAWAL")?;
+    let escrowed = pre_balances.lamports("escrow").unwrap_or_default();
+
+    // Calculate expected escrow total (1 SOL * 5 payments = 5 SOL)
+    let expected_escrow_total = LAMPORTS_PER_SOL * 5;
+
+    // Get the rent amount
+    let rent_exemption = context
+        .client
+        .get_minimum_balance_for_rent_exemption(EscrowAccount::default().try_to_vec()?.len())?;
+    println!(
+        "Rent amount: {} SOL",
+        balances::format_sol_balance(rent_exemption)
+    );
+
+    // Create withdraw instruction with validation data above threshold
+    let args = WithdrawFundsArgs {
+        validation_data: 2000, // Higher than threshold of 1000
+    };
+    let signature = context
+        .withdraw_funds(
+            &subscription_pda,
+            &context.seller,
+            &context.buyer.pubkey(),
+            args,
+        )
+        .await?;
+    println!(
+        "✅ Withdrawal transaction confirmed. Signature: {}",
+        signature
+    );
+
+    context.log_snapshot(&mut tracker, "AFTER FAILED WITHDRAWAL")?;
+    let delta = tracker.last_delta()?;
+    delta.print();
+
+    // The escrow's payments and rent all go back to the buyer
+    let acceptable_range = LAMPORTS_PER_SOL / 100; // Tolerance for rent (0.01 SOL)
+    delta.expect(
+        "buyer",
+        (expected_escrow_total + rent_exemption) as i64,
+        acceptable_range,
+    )?;
+    tracker.expect_delta("buyer", escrowed as i64)?;
+    tracker.expect_delta("escrow", -(escrowed as i64))?;
+
+    // The seller only pays the fee, which includes any priority fee
+    // requested through the compute budget
+    let fee = context.fee_paid(&signature) as i64;
+    tracker.expect_delta("seller", -fee)?;
+
+    // Verify escrow account is closed
+    expect(context)
+        .escrow(&subscription_pda)
+        .closed()
+        .holds()
+        .await?;
+
+    println!("\n✅ Failed withdrawal test completed successfully!");
+    println!(
+        "   Funds returned to buyer: {} SOL",
+        balances::format_sol(delta.get("buyer").unwrap_or_default())
+    );
+
+    Ok(())
+}
+
+async fn test_subscription_history(
+    context: &TestContext,
+    subscription_id: &str,
+    history_start: Option<Signature>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Subscription History...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    let history = history::fetch_history(
+        &context.client,
+        &context.program_id,
+        &subscription_pda,
+        history_start,
+    )?;
+    history::print_history(&subscription_pda, &history);
+
+    // Start, five escrowed and two direct payments, cancel, refund
+    assert_eq!(
+        history.len(),
+        10,
+        "Expected 10 events, found {}",
+        history.len()
+    );
+    assert_eq!(
+        history[0].event,
+        EscrowEvent::Started {
+            subscription_id: subscription_id.to_string(),
+            validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+        }
+    );
+    for (i, entry) in history[1..8].iter().enumerate() {
+        let destination = if i < 5 {
+            PaymentDestination::Escrow
+        } else {
+            PaymentDestination::Direct
+        };
+        assert_eq!(
+            entry.event,
+            EscrowEvent::Payment {
+                amount: LAMPORTS_PER_SOL,
+                destination,
+            },
+            "Unexpected event for payment {}",
+            i + 1
+        );
+    }
+    assert_eq!(history[8].event, EscrowEvent::Cancelled);
+    assert_eq!(
+        history[9].event,
+        EscrowEvent::Withdrawal {
+            validation_data: 2000,
+            outcome: WithdrawalOutcome::RefundedToBuyer,
+        }
+    );
+
+    // The refund returns everything the escrow ever held
+    let escrowed: i64 = history[..9].iter().map(|entry| entry.movement.escrow).sum();
+    assert_eq!(
+        history[9].movement.escrow, -escrowed,
+        "Refund should empty the escrow"
+    );
+    assert_eq!(history[9].movement.buyer, escrowed);
+
+    let slots: Vec<u64> = history.iter().map(|entry| entry.slot).collect();
+    assert!(
+        slots.windows(2).all(|pair| pair[0] <= pair[1]),
+        "History should be ordered oldest first"
+    );
+
+    println!(
+        "✅ Subscription history reconstructed. PDA: {}",
+        subscription_pda
+    );
+
+    Ok(())
+}
+
+async fn test_indexer(
+    context: &TestContext,
+    subscription_id: &str,
+    history_start: Option<Signature>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Indexer...");
+    let (subscription_pda, _) = context.find_subscription_pda(subscription_id);
+
+    let mut indexer = Indexer::open_in_memory()?;
+    let indexed = indexer.backfill(
+        &context.client,
+        &context.program_id,
+        &subscription_pda,
+        history_start,
+    )?;
+    assert_eq!(
+        indexed, 10,
+        "Expected 10 indexed instructions, found {}",
+        indexed
+    );
+
+    // A second backfill overlaps the first entirely
+    let reindexed = indexer.backfill(
+        &context.client,
+        &context.program_id,
+        &subscription_pda,
+        history_start,
+    )?;
+    assert_eq!(
+        reindexed, 0,
+        "Backfill should not index an instruction twice"
+    );
+
+    let subscription = indexer
+        .subscription(&subscription_pda)?
+        .ok_or("Subscription missing from index")?;
+    assert_eq!(subscription.subscription_id, subscription_id);
+    assert_eq!(subscription.buyer, context.buyer.pubkey());
+    assert_eq!(subscription.seller, context.seller.pubkey());
+    assert_eq!(subscription.payment_count, 7);
+    assert_eq!(subscription.total_amount, LAMPORTS_PER_SOL * 5);
+    assert!(!subscription.is_active && subscription.closed);
+
+    assert!(
+        indexer
+            .open_subscriptions(Some(&context.seller.pubkey()))?
+            .is_empty(),
+        "Withdrawn subscription should not be open"
+    );
+    assert_eq!(indexer.total_escrowed()?, 0);
+
+    let history = history::fetch_history(
+        &context.client,
+        &context.program_id,
+        &subscription_pda,
+        history_start,
+    )?;
+    let refunds = indexer.refunds()?;
+    assert_eq!(refunds.len(), 1, "Expected one refund");
+    assert_eq!(refunds[0].signature, history[9].signature);
+    assert_eq!(refunds[0].buyer, context.buyer.pubkey());
+    assert_eq!(refunds[0].amount as i64, history[9].movement.buyer);
+
+    println!("✅ Subscription indexed. PDA: {}", subscription_pda);
+
+    Ok(())
+}
+
+async fn test_successful_withdrawal(
+    context: &TestContext,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Successful Withdrawal...");
+
+    // Generate new keypairs for buyer and seller
+    let new_buyer = context.keypair("withdrawal/buyer");
+    let new_seller = context.keypair("withdrawal/seller");
+
+    // Fund new accounts using the same method as setup
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    println!("\nFunding new seller account...");
+    context
+        .request_airdrop_with_confirmation(&new_seller.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+
+    // Verify initial balances
+    let buyer_balance = context.client.get_balance(&new_buyer.pubkey())?;
+    let seller_balance = context.client.get_balance(&new_seller.pubkey())?;
+
+    println!("\nInitial balances:");
+    println!(
+        "New Buyer: {} SOL",
+        balances::format_sol_balance(buyer_balance)
+    );
+    println!(
+        "New Seller: {} SOL",
+        balances::format_sol_balance(seller_balance)
+    );
+
+    // Generate new subscription for this test
+    let subscription_id = context.subscription_id("premium_content_2");
+    let (subscription_pda, _) = escrow::find_escrow_pda(
+        &context.program_id,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &subscription_id,
+    );
+
+    // Start subscription
+    println!("\nStarting new subscription...");
+    let start_args = StartSubscriptionArgs {
+        subscription_id: subscription_id.clone(),
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let signature = context
+        .start_subscription(
+            &subscription_pda,
+            &new_buyer,
+            &new_seller.pubkey(),
+            start_args,
+        )
+        .await?;
+    println!("✅ Subscription started. Signature: {}", signature);
+
+    // Make 5 payments
+    let payment_amount = LAMPORTS_PER_SOL; // 1 SOL per payment
+    for i in 0..5 {
+        println!("\nMaking payment {} of 5...", i + 1);
+        let args = MakePaymentArgs {
+            amount: payment_amount,
+        };
+        let signature = context
+            .make_payment(&subscription_pda, &new_buyer, &new_seller.pubkey(), args)
+            .await?;
+        println!("✅ Payment {} completed. Signature: {}", i + 1, signature);
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+
+    // Cancel subscription
+    println!("\nCancelling subscription...");
+    let signature = context
+        .cancel_subscription(&subscription_pda, &new_buyer, &new_seller.pubkey())
+        .await?;
+    println!("✅ Subscription cancelled. Signature: {}", signature);
+
+    // Get pre-withdrawal balances
+    let mut tracker = BalanceTracker::new([
+        ("seller", new_seller.pubkey()),
+        ("escrow", subscription_pda),
+        ("buyer", new_buyer.pubkey()),
+    ]);
+    let pre_withdrawal = context.log_snapshot(&mut tracker, "PRE-WITHDRAWAL")?;
+    let escrowed = pre_withdrawal.lamports("escrow").unwrap_or_default();
+
+    // Get the rent amount
+    let rent_exemption = context
+        .client
+        .get_minimum_balance_for_rent_exemption(EscrowAccount::default().try_to_vec()?.len())?;
+
+    // Execute successful withdrawal
+    println!("\nExecuting withdrawal with valid validation data...");
+    let args = WithdrawFundsArgs {
+        validation_data: 500, // Lower than threshold of 1000
+    };
+    let signature = context
+        .withdraw_funds(&subscription_pda, &new_seller, &new_buyer.pubkey(), args)
+        .await?;
+    println!(
+        "✅ Withdrawal transaction confirmed. Signature: {}",
+        signature
+    );
+
+    context.log_snapshot(&mut tracker, "POST-WITHDRAWAL")?;
+    let delta = tracker.delta("PRE-WITHDRAWAL", "POST-WITHDRAWAL")?;
+    delta.print();
+
+    // Seller receives the escrowed payments, less the fee they paid as fee payer
+    let fee = context.fee_paid(&signature) as i64;
+    tracker.expect_delta("seller", lamports_from_sol(5.0) - fee)?;
+
+    // Buyer receives the rent, and the escrow account is closed
+    let acceptable_range = LAMPORTS_PER_SOL / 100; // 0.01 SOL tolerance
+    delta.expect("buyer", rent_exemption as i64, acceptable_range)?;
+    tracker.expect_delta("escrow", -(escrowed as i64))?;
+    expect(context)
+        .escrow(&subscription_pda)
+        .closed()
+        .holds()
+        .await?;
+
+    println!("\n✅ Successful withdrawal test completed!");
+    println!(
+        "   Seller received: {} SOL",
+        balances::format_sol(delta.get("seller").unwrap_or_default())
+    );
+    println!(
+        "   Buyer received rent: {} SOL",
+        balances::format_sol(delta.get("buyer").unwrap_or_default())
+    );
+
+    Ok(())
+}
+
+async fn test_durable_nonce_payments(
+    context: &TestContext,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Durable Nonce Payments...");
+
+    let new_buyer = context.keypair("nonce/buyer");
+    let new_seller = context.keypair("nonce/seller");
+
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    println!("\nFunding new seller account...");
+    context
+        .request_airdrop_with_confirmation(&new_seller.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+
+    let subscription_id = context.subscription_id("premium_content_nonce");
+    let (subscription_pda, _) = escrow::find_escrow_pda(
+        &context.program_id,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &subscription_id,
+    );
+
+    // Start subscription with a regular blockhash
+    let start_args = StartSubscriptionArgs {
+        subscription_id: subscription_id.clone(),
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let instruction = escrow::start_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &start_args,
+    )?;
+    let signature = context.sign_and_send("start_subscription", &[instruction], &new_buyer)?;
+    println!("✅ Subscription started. Signature: {}", signature);
+
+    // A seeded rerun finds the nonce account it created before, and uses it
+    // from whatever nonce it holds now
+    let nonce_keypair = context.keypair("nonce/account");
+    let nonce_account = if context
+        .client
+        .get_account_with_commitment(&nonce_keypair.pubkey(), context.client.commitment())?
+        .value
+        .is_some()
+    {
+        println!("Reusing nonce account {}", nonce_keypair.pubkey());
+        nonce_keypair.pubkey()
+    } else {
+        let nonce_account = nonce::create_nonce_account(
+            &context.client,
+            &new_buyer,
+            &nonce_keypair,
+            &new_buyer.pubkey(),
+        )?;
+        println!("✅ Nonce account created: {}", nonce_account);
+        nonce_account
+    };
+
+    // Sign the payment against the stored nonce, as the buyer would offline
+    let nonce = nonce::fetch_nonce(&context.client, &nonce_account)?;
+    let signing_blockhash = context.client.get_latest_blockhash()?;
+    let instruction = escrow::make_payment(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &MakePaymentArgs {
+            amount: LAMPORTS_PER_SOL,
+        },
+    )?;
+    let signed_payment = context.transaction_builder.borrow().sign_with_nonce(
+        &[instruction],
+        &new_buyer.pubkey(),
+        &[&new_buyer],
+        &nonce_account,
+        &new_buyer.pubkey(),
+        nonce,
+    )?;
+    let encoded_payment = transaction::encode_transaction(&signed_payment)?;
+    println!("Payment signed offline against nonce {}", nonce);
+
+    // Submit only once a blockhash fetched at signing time would have expired
+    println!("Waiting for the signing-time blockhash to expire...");
+    while context
+        .client
+        .is_blockhash_valid(&signing_blockhash, context.client.commitment())?
+    {
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+
+    let payment = transaction::decode_transaction(&encoded_payment)?;
+    let signature = context.send_transaction("make_payment", &payment)?;
+    println!("✅ Nonce payment submitted. Signature: {}", signature);
+
+    let advanced_nonce = nonce::fetch_nonce(&context.client, &nonce_account)?;
+    assert_ne!(
+        advanced_nonce, nonce,
+        "Nonce should advance once the payment is processed"
+    );
+    assert!(
+        context
+            .client
+            .send_and_confirm_transaction(&payment)
+            .is_err(),
+        "Replaying a nonce transaction should be rejected"
+    );
+
+    // Cancel the same way, against the advanced nonce
+    let instruction = escrow::cancel_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+    );
+    let signed_cancel = context.transaction_builder.borrow().sign_with_nonce(
+        &[instruction],
+        &new_buyer.pubkey(),
+        &[&new_buyer],
+        &nonce_account,
+        &new_buyer.pubkey(),
+        advanced_nonce,
+    )?;
+    let encoded_cancel = transaction::encode_transaction(&signed_cancel)?;
+
+    let cancel = transaction::decode_transaction(&encoded_cancel)?;
+    let signature = context.send_transaction("cancel_subscription", &cancel)?;
+    println!("✅ Nonce cancellation submitted. Signature: {}", signature);
+
+    expect(context)
+        .escrow(&subscription_pda)
+        .payment_count(1)
+        .is_active(false)
+        .holds()
+        .await?;
+
+    context
+        .close_escrow(&subscription_pda, &new_buyer, &new_seller)
+        .await?;
+
+    println!("\n✅ Durable nonce test completed!");
+
+    Ok(())
+}
+
+async fn test_sponsored_withdrawal(
+    context: &TestContext,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Sponsored Withdrawal...");
+
+    let new_buyer = context.keypair("sponsored/buyer");
+    let new_seller = context.keypair("sponsored/seller");
+    let sponsor = context.keypair("sponsored/sponsor");
+
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    println!("\nFunding new seller account...");
+    context
+        .request_airdrop_with_confirmation(&new_seller.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+
+    println!("\nFunding sponsor account...");
+    context
+        .request_airdrop_with_confirmation(&sponsor.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+
+    let subscription_id = context.subscription_id("premium_content_sponsored");
+    let (subscription_pda, _) = escrow::find_escrow_pda(
+        &context.program_id,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &subscription_id,
+    );
+
+    // Start, pay into escrow and cancel as in the successful withdrawal test
+    let start_args = StartSubscriptionArgs {
+        subscription_id: subscription_id.clone(),
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let instruction = escrow::start_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &start_args,
+    )?;
+    let signature = context.sign_and_send("start_subscription", &[instruction], &new_buyer)?;
+    println!("✅ Subscription started. Signature: {}", signature);
+
+    for i in 0..5 {
+        let args = MakePaymentArgs {
+            amount: LAMPORTS_PER_SOL,
+        };
+        let instruction = escrow::make_payment(
+            &context.program_id,
+            &subscription_pda,
+            &new_buyer.pubkey(),
+            &new_seller.pubkey(),
+            &args,
+        )?;
+        let signature = context.sign_and_send("make_payment", &[instruction], &new_buyer)?;
+        println!("✅ Payment {} completed. Signature: {}", i + 1, signature);
+    }
+
+    let instruction = escrow::cancel_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+    );
+    let signature = context.sign_and_send("cancel_subscription", &[instruction], &new_buyer)?;
+    println!("✅ Subscription cancelled. Signature: {}", signature);
+
+    let escrowed = context
+        .fetch_escrow_account(&subscription_pda)
+        .await?
+        .total_amount;
+    let seller_before = context.get_balance(&new_seller.pubkey())?;
+    let sponsor_before = context.get_balance(&sponsor.pubkey())?;
+
+    // The sponsor pays the fee; the seller only authorizes the withdrawal
+    let args = WithdrawFundsArgs {
+        validation_data: 500, // Lower than threshold of 1000
+    };
+    let instruction = escrow::withdraw_funds(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &new_seller.pubkey(),
+        &args,
+    )?;
+    let message = context.transaction_builder.borrow().message(
+        &context.client,
+        &[instruction],
+        &sponsor.pubkey(),
+    )?;
+    let unsigned = PartialTransaction::new(message).encode()?;
+
+    // Seller signs their copy and hands back only the base58 signature
+    let mut seller_copy = PartialTransaction::decode(&unsigned)?;
+    let seller_signature = seller_copy.sign(&new_seller)?.to_string();
+    assert_eq!(seller_copy.missing_signers(), vec![sponsor.pubkey()]);
+    assert!(
+        seller_copy.clone().into_transaction().is_err(),
+        "A transaction missing the fee payer's signature should not be submittable"
+    );
+
+    // Sponsor applies the seller's signature, adds their own and submits
+    let mut sponsor_copy = PartialTransaction::decode(&unsigned)?;
+    sponsor_copy.add_signature(&new_seller.pubkey(), seller_signature.parse()?)?;
+    assert!(
+        sponsor_copy
+            .add_signature(&new_buyer.pubkey(), seller_signature.parse()?)
+            .is_err(),
+        "The buyer is not a signer of the withdrawal"
+    );
+    sponsor_copy.sign(&sponsor)?;
+    assert!(sponsor_copy.is_complete());
+
+    let transaction = PartialTransaction::decode(&sponsor_copy.encode()?)?.into_transaction()?;
+    let signature = context.send_transaction("withdraw_funds", &transaction)?;
+    println!(
+        "✅ Sponsored withdrawal confirmed. Signature: {}",
+        signature
+    );
+
+    let seller_after = context.get_balance(&new_seller.pubkey())?;
+    let sponsor_after = context.get_balance(&sponsor.pubkey())?;
+
+    assert_eq!(
+        seller_after - seller_before,
+        escrowed,
+        "Seller should receive the full escrow without paying the fee"
+    );
+    assert_eq!(
+        sponsor_before - sponsor_after,
+        context.fee_paid(&signature),
+        "Sponsor should pay exactly the transaction fee"
+    );
+    assert_eq!(
+        context.get_balance(&subscription_pda)?,
+        0,
+        "Escrow account should be closed"
+    );
+
+    println!("\n✅ Sponsored withdrawal test completed!");
+    println!(
+        "   Seller received: {} SOL",
+        balances::format_sol_balance(escrowed)
+    );
+
+    Ok(())
+}
+
+async fn test_batched_payments(context: &TestContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Batched Payments...");
+
+    let new_buyer = context.keypair("batched/buyer");
+    let first_seller = context.keypair("batched/first_seller");
+    let second_seller = context.keypair("batched/second_seller");
+
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    println!("\nFunding new seller accounts...");
+    context
+        .request_airdrop_with_confirmation(&first_seller.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+    context
+        .request_airdrop_with_confirmation(&second_seller.pubkey(), SELLER_INITIAL_BALANCE)
+        .await?;
+
+    // One subscription per seller, both paid by the same buyer
+    let subscription_id = context.subscription_id("premium_content_batched");
+    let mut subscriptions = Vec::new();
+    for seller in [&first_seller, &second_seller] {
+        let (subscription_pda, _) = escrow::find_escrow_pda(
+            &context.program_id,
+            &new_buyer.pubkey(),
+            &seller.pubkey(),
+            &subscription_id,
+        );
+        let args = StartSubscriptionArgs {
+            subscription_id: subscription_id.clone(),
+            validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+        };
+        let instruction = escrow::start_subscription(
+            &context.program_id,
+            &subscription_pda,
+            &new_buyer.pubkey(),
+            &seller.pubkey(),
+            &args,
+        )?;
+        let signature = context.sign_and_send("start_subscription", &[instruction], &new_buyer)?;
+        println!("✅ Subscription started. Signature: {}", signature);
+        subscriptions.push((subscription_pda, seller.pubkey()));
+    }
+
+    let payment = |(subscription_pda, seller): &(Pubkey, Pubkey), amount: u64| {
+        escrow::make_payment(
+            &context.program_id,
+            subscription_pda,
+            &new_buyer.pubkey(),
+            seller,
+            &MakePaymentArgs { amount },
+        )
+    };
+    let payment_amount = LAMPORTS_PER_SOL / 10;
+
+    // A batch with a payment the buyer cannot afford must leave no trace
+    let mut instructions = vec![payment(&subscriptions[0], payment_amount)?];
+    instructions.push(payment(&subscriptions[0], BUYER_INITIAL_BALANCE * 2)?);
+    assert!(
+        context
+            .sign_and_send("make_payment_batch", &instructions, &new_buyer)
+            .is_err(),
+        "Batch with an unaffordable payment should fail"
+    );
+    let escrow_account = escrow::fetch_escrow_account(&context.client, &subscriptions[0].0)?
+        .ok_or("Escrow account missing")?;
+    assert_eq!(
+        escrow_account.payment_count, 0,
+        "Failed batch should not record any payment"
+    );
+
+    // Seven payments on the first subscription cross from escrow to direct
+    // payments mid-batch; two more go to the second subscription's escrow
+    let mut instructions = Vec::new();
+    for _ in 0..7 {
+        instructions.push(payment(&subscriptions[0], payment_amount)?);
+    }
+    for _ in 0..2 {
+        instructions.push(payment(&subscriptions[1], payment_amount)?);
+    }
+    let batches = context
+        .transaction_builder
+        .borrow()
+        .pack(&instructions, &new_buyer.pubkey())?;
+    assert_eq!(
+        batches.len(),
+        1,
+        "Nine payments should fit in one transaction"
+    );
+
+    let first_escrow_before = context.get_balance(&subscriptions[0].0)?;
+    let second_escrow_before = context.get_balance(&subscriptions[1].0)?;
+    let first_seller_before = context.get_balance(&first_seller.pubkey())?;
+    let second_seller_before = context.get_balance(&second_seller.pubkey())?;
+
+    let signature = context.sign_and_send("make_payment_batch", &batches[0], &new_buyer)?;
+    println!(
+        "✅ Batch of {} payments confirmed. Signature: {}",
+        instructions.len(),
+        signature
+    );
+
+    let first_escrow = escrow::fetch_escrow_account(&context.client, &subscriptions[0].0)?
+        .ok_or("Escrow account missing")?;
+    let second_escrow = escrow::fetch_escrow_account(&context.client, &subscriptions[1].0)?
+        .ok_or("Escrow account missing")?;
+
+    assert_eq!(first_escrow.payment_count, 7);
+    assert_eq!(first_escrow.total_amount, payment_amount * 5);
+    assert_eq!(second_escrow.payment_count, 2);
+    assert_eq!(second_escrow.total_amount, payment_amount * 2);
+
+    assert_eq!(
+        context.get_balance(&subscriptions[0].0)? - first_escrow_before,
+        payment_amount * 5,
+        "First five payments should go to escrow"
+    );
+    assert_eq!(
+        context.get_balance(&first_seller.pubkey())? - first_seller_before,
+        payment_amount * 2,
+        "Payments after the fifth should go directly to the seller"
+    );
+    assert_eq!(
+        context.get_balance(&subscriptions[1].0)? - second_escrow_before,
+        payment_amount * 2,
+        "Second subscription payments should go to escrow"
+    );
+    assert_eq!(
+        context.get_balance(&second_seller.pubkey())?,
+        second_seller_before,
+        "Second seller should not be paid directly"
+    );
+
+    // Far more payments than fit in one transaction are split in order
+    let many: Vec<Instruction> = (0..60)
+        .map(|_| payment(&subscriptions[1], payment_amount))
+        .collect::<Result<_, _>>()?;
+    let batches = context
+        .transaction_builder
+        .borrow()
+        .pack(&many, &new_buyer.pubkey())?;
+    assert!(
+        batches.len() > 1,
+        "Sixty payments should need several transactions"
+    );
+    assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), many.len());
+
+    for ((subscription_pda, _), seller) in subscriptions.iter().zip([&first_seller, &second_seller])
+    {
+        context
+            .close_escrow(subscription_pda, &new_buyer, seller)
+            .await?;
+    }
+
+    println!("\n✅ Batched payments test completed!");
+
+    Ok(())
+}
+
+/// Sends a payment as a v0 transaction that loads the escrow and seller from
+/// an address lookup table, whatever `--tx-version` the run uses, and checks
+/// the loaded addresses resolve to the accounts the cluster used.
+async fn test_lookup_table_payment(
+    context: &TestContext,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting v0 payment through an address lookup table...");
+
+    let new_buyer = context.keypair("lookup_table/buyer");
+    let seller = context.seller.pubkey();
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    let subscription_id = context.subscription_id("premium_content_v0");
+    let (subscription_pda, _) = escrow::find_escrow_pda(
+        &context.program_id,
+        &new_buyer.pubkey(),
+        &seller,
+        &subscription_id,
+    );
+    let args = StartSubscriptionArgs {
+        subscription_id,
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    context
+        .start_subscription(&subscription_pda, &new_buyer, &seller, args)
+        .await?;
+
+    let lookup_table = transaction::create_lookup_table(
+        &context.client,
+        &new_buyer,
+        vec![subscription_pda, seller, system_program::id()],
+    )?;
+    println!("✅ Lookup table created: {}", lookup_table.key);
+    let builder = TransactionBuilder::default()
+        .with_version(TransactionVersion::V0)
+        .lookup_table(lookup_table);
+    let payment = escrow::make_payment(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &seller,
+        &MakePaymentArgs {
+            amount: LAMPORTS_PER_SOL / 10,
+        },
+    )?;
+    let transaction = builder.build(
+        &context.client,
+        &[payment],
+        &new_buyer.pubkey(),
+        &[&new_buyer],
+    )?;
+    let VersionedMessage::V0(message) = &transaction.message else {
+        return Err("Payment should compile to a v0 message".into());
+    };
+    assert!(
+        !message.address_table_lookups.is_empty(),
+        "Payment should load accounts from the lookup table"
+    );
+    let signature = context.send_transaction("make_payment_v0", &transaction)?;
+    println!("✅ v0 payment confirmed. Signature: {}", signature);
+
+    // The cluster reports the addresses it loaded; resolving the lookups from
+    // the table itself must agree
+    let confirmed = transaction::fetch_confirmed_transaction(&context.client, &signature)?;
+    let account_keys =
+        transaction::resolve_account_keys(&context.client, &confirmed.transaction.message)?;
+    assert_eq!(
+        account_keys, confirmed.account_keys,
+        "Resolved account keys differ from the addresses the cluster loaded"
+    );
+    let static_keys = confirmed.transaction.message.static_account_keys();
+    for address in [subscription_pda, seller] {
+        assert!(
+            !static_keys.contains(&address) && account_keys.contains(&address),
+            "{} should be loaded from the lookup table",
+            address
+        );
+    }
+    let decoded = decoder::decode_message(
+        &context.program_id,
+        &confirmed.transaction.message,
+        &account_keys,
+    );
+    assert_eq!(decoded.len(), 1, "Expected 1 escrow instruction");
+    assert_eq!(
+        decoded[0].account(AccountRole::Escrow),
+        Some(&subscription_pda)
+    );
+    assert_eq!(decoded[0].account(AccountRole::Seller), Some(&seller));
+    println!("✅ Lookup table addresses resolved");
+
+    expect(context)
+        .escrow(&subscription_pda)
+        .is_active(true)
+        .payment_count(1)
+        .holds()
+        .await?;
+
+    context
+        .close_escrow(&subscription_pda, &new_buyer, &context.seller)
+        .await?;
+
+    println!("\n✅ Lookup table payment test completed!");
+
+    Ok(())
+}
+
+async fn test_resubmission(context: &TestContext) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\nTesting Resubmission...");
+
+    let new_buyer = context.keypair("resubmission/buyer");
+    println!("\nFunding new buyer account...");
+    context
+        .request_airdrop_with_confirmation(&new_buyer.pubkey(), BUYER_INITIAL_BALANCE)
+        .await?;
+
+    let subscription_id = context.subscription_id("premium_content_resubmitted");
+    let (subscription_pda, _) = escrow::find_escrow_pda(
+        &context.program_id,
+        &new_buyer.pubkey(),
+        &context.seller.pubkey(),
+        &subscription_id,
+    );
+    let args = StartSubscriptionArgs {
+        subscription_id,
+        validation_threshold: DEFAULT_VALIDATION_THRESHOLD,
+    };
+    let instructions = [escrow::start_subscription(
+        &context.program_id,
+        &subscription_pda,
+        &new_buyer.pubkey(),
+        &context.seller.pubkey(),
+        &args,
+    )?];
+
+    // The first attempt is signed against a blockhash the cluster never
+    // produced, standing in for one that expired in flight
+    let mut blockhashes = Vec::new();
+    let signature =
+        submit::send_with_resign(&context.client, &context.retry_policy, |blockhash| {
+            let blockhash = if blockhashes.is_empty() {
+                Hash::default()
+            } else {
+                blockhash
+            };
+            blockhashes.push(blockhash);
+            context.transaction_builder.borrow().build_with_blockhash(
+                &instructions,
+                &new_buyer.pubkey(),
+                &[&new_buyer],
+                blockhash,
+            )
+        })?;
+    println!(
+        "✅ Re-signed transaction confirmed. Signature: {}",
+        signature
+    );
+    assert_eq!(
+        blockhashes.len(),
+        2,
+        "Expired attempt should be re-signed once"
+    );
+
+    // Submitting the confirmed transaction again must not execute it twice
+    let transaction = context.transaction_builder.borrow().build_with_blockhash(
+        &instructions,
+        &new_buyer.pubkey(),
+        &[&new_buyer],
+        blockhashes[1],
+    )?;
+    assert_eq!(transaction.signatures[0], signature);
+    let resubmitted =
+        submit::send_with_retry(&context.client, &transaction, &context.retry_policy)?;
+    assert_eq!(
+        resubmitted, signature,
+        "Resubmission should report the original signature"
+    );
+
+    let escrow_account = escrow::fetch_escrow_account(&context.client, &subscription_pda)?
+        .ok_or("Escrow account missing")?;
+    assert!(escrow_account.is_active);
+    assert_eq!(escrow_account.payment_count, 0);
+
+    context
+        .close_escrow(&subscription_pda, &new_buyer, &context.seller)
+        .await?;
+
+    println!("\n✅ Resubmission test completed!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        options: HarnessOptions,
+    }
+
+    fn parse(args: &[&str]) -> Result<HarnessOptions, clap::Error> {
+        <Cli as clap::Parser>::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .map(|cli| cli.options)
+    }
+
+    #[test]
+    fn derives_keypairs_from_seed_and_name() {
+        let buyer = new_keypair(Some(7), "buyer");
+        assert_eq!(buyer.to_bytes(), new_keypair(Some(7), "buyer").to_bytes());
+        assert_ne!(buyer.pubkey(), new_keypair(Some(7), "seller").pubkey());
+        assert_ne!(buyer.pubkey(), new_keypair(Some(8), "buyer").pubkey());
+        assert_ne!(
+            new_keypair(None, "buyer").pubkey(),
+            new_keypair(None, "buyer").pubkey(),
+            "Unseeded keypairs should be random"
+        );
+    }
+
+    #[test]
+    fn derives_subscription_ids_from_seed_and_name() {
+        let subscription_id = seeded_subscription_id(7, "premium_content");
+        assert_eq!(
+            subscription_id,
+            seeded_subscription_id(7, "premium_content")
+        );
+        assert_ne!(
+            subscription_id,
+            seeded_subscription_id(8, "premium_content")
+        );
+        assert_ne!(
+            subscription_id,
+            seeded_subscription_id(7, "premium_content_2")
+        );
+        assert!(subscription_id.len() <= solana_sdk::pubkey::MAX_SEED_LEN);
+    }
+
+    #[test]
+    fn runs_each_client_once() {
+        assert_eq!(
+            parse(&[]).unwrap().clients(),
+            vec![ClientPath::Raw, ClientPath::Anchor]
+        );
+        assert_eq!(
+            parse(&["--simulate-first"]).unwrap().clients(),
+            vec![ClientPath::Raw],
+            "Modes anchor_client can't take part in should default to raw alone"
+        );
+        let options =
+            parse(&["--client", "raw,anchor,raw", "--report", "out/report.json"]).unwrap();
+        assert_eq!(
+            options.clients(),
+            vec![ClientPath::Raw, ClientPath::Anchor],
+            "Expected each client once, in the order given"
+        );
+        assert_eq!(
+            options.report_path(ClientPath::Anchor),
+            Some(PathBuf::from("out/report.anchor.json"))
+        );
+        let options = parse(&["--client", "anchor", "--report", "report.txt"]).unwrap();
+        assert_eq!(
+            options.report_path(ClientPath::Anchor),
+            Some(PathBuf::from("report.txt")),
+            "A single client keeps the report path as given"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_clients() {
+        assert!(parse(&["--client", "raw,bogus"]).is_err());
+    }
+}
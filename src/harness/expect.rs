@@ -0,0 +1,243 @@
+//! Fluent assertions on escrow state, e.g.
+//! `expect(context).escrow(&pda).since(&before).is_active(false).lamports_delta(0).holds().await?`.
+//!
+//! Expectations are collected first and checked together against one fetch of
+//! the account, so a failure lists every mismatch along with the account's
+//! state before and after.
+
+use super::TestContext;
+use crate::{
+    balances::{self, format_sol, format_sol_balance},
+    escrow::{EscrowAccount, LAMPORTS_PER_SOL},
+    layout,
+    program::ClientPath,
+};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// `amount` SOL in lamports, for lamport expectations.
+pub fn lamports_from_sol(amount: f64) -> i64 {
+    (amount * LAMPORTS_PER_SOL as f64).round() as i64
+}
+
+pub fn expect(context: &TestContext) -> Expect<'_> {
+    Expect { context }
+}
+
+pub struct Expect<'a> {
+    context: &'a TestContext,
+}
+
+impl<'a> Expect<'a> {
+    pub fn escrow(self, pda: &Pubkey) -> EscrowExpectation<'a> {
+        EscrowExpectation {
+            context: self.context,
+            pda: *pda,
+            before: None,
+            checks: Vec::new(),
+        }
+    }
+}
+
+/// An escrow PDA's lamports and decoded account, `None` once closed.
+#[derive(Clone, Serialize)]
+pub struct EscrowState {
+    #[serde(with = "crate::json::lamports")]
+    pub lamports: u64,
+    pub account: Option<EscrowAccount>,
+}
+
+impl EscrowState {
+    /// Reads the PDA with a single RPC request, so its lamports and decoded
+    /// account describe the same slot. Under `--client anchor` the account
+    /// is read and decoded through anchor_client.
+    pub async fn fetch(
+        context: &TestContext,
+        pda: &Pubkey,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if context.client_path == ClientPath::Anchor {
+            return Ok(match context.anchor.escrow_state(pda).await? {
+                Some((lamports, account)) => Self {
+                    lamports,
+                    account: Some(account),
+                },
+                None => Self {
+                    lamports: 0,
+                    account: None,
+                },
+            });
+        }
+        let account = context
+            .client
+            .get_account_with_commitment(pda, context.client.commitment())?
+            .value;
+        Ok(match account {
+            Some(account) => Self {
+                lamports: account.lamports,
+                account: Some(layout::decode_escrow_account(&account.data)?.account),
+            },
+            None => Self {
+                lamports: 0,
+                account: None,
+            },
+        })
+    }
+}
+
+/// Describes a mismatch in one field of the account, if there is one.
+type FieldCheck = Box<dyn Fn(&EscrowAccount) -> Option<String>>;
+
+enum Check {
+    Closed,
+    Field(FieldCheck),
+    LamportsDelta { expected: i64, tolerance: u64 },
+}
+
+pub struct EscrowExpectation<'a> {
+    context: &'a TestContext,
+    pda: Pubkey,
+    before: Option<EscrowState>,
+    checks: Vec<Check>,
+}
+
+impl EscrowExpectation<'_> {
+    /// State to compare lamports against and show in failures.
+    pub fn since(mut self, before: &EscrowState) -> Self {
+        self.before = Some(before.clone());
+        self
+    }
+
+    pub fn closed(mut self) -> Self {
+        self.checks.push(Check::Closed);
+        self
+    }
+
+    fn field(mut self, check: impl Fn(&EscrowAccount) -> Option<String> + 'static) -> Self {
+        self.checks.push(Check::Field(Box::new(check)));
+        self
+    }
+
+    // Named after the account field, like the other expectations
+    #[allow(clippy::wrong_self_convention)]
+    pub fn is_active(self, is_active: bool) -> Self {
+        self.field(move |account| mismatch("is_active", &is_active, &account.is_active))
+    }
+
+    pub fn payment_count(self, payment_count: u8) -> Self {
+        self.field(move |account| mismatch("payment_count", &payment_count, &account.payment_count))
+    }
+
+    pub fn total_amount(self, lamports: u64) -> Self {
+        self.field(move |account| {
+            (account.total_amount != lamports).then(|| {
+                format!(
+                    "total_amount: expected {} SOL, found {} SOL",
+                    format_sol_balance(lamports),
+                    format_sol_balance(account.total_amount)
+                )
+            })
+        })
+    }
+
+    pub fn subscription_id(self, subscription_id: &str) -> Self {
+        let subscription_id = subscription_id.to_string();
+        self.field(move |account| {
+            mismatch(
+                "subscription_id",
+                &subscription_id,
+                &account.subscription_id,
+            )
+        })
+    }
+
+    pub fn buyer(self, buyer: &Pubkey) -> Self {
+        let buyer = *buyer;
+        self.field(move |account| mismatch("buyer", &buyer, &account.buyer))
+    }
+
+    pub fn seller(self, seller: &Pubkey) -> Self {
+        let seller = *seller;
+        self.field(move |account| mismatch("seller", &seller, &account.seller))
+    }
+
+    /// Exact change in the PDA's lamports since the `since` state.
+    pub fn lamports_delta(self, expected: i64) -> Self {
+        self.lamports_delta_within(expected, 0)
+    }
+
+    pub fn lamports_delta_within(mut self, expected: i64, tolerance: u64) -> Self {
+        self.checks.push(Check::LamportsDelta {
+            expected,
+            tolerance,
+        });
+        self
+    }
+
+    /// Fetches the account and checks every expectation, returning the state
+    /// it was checked against.
+    pub async fn holds(self) -> Result<EscrowState, Box<dyn std::error::Error>> {
+        let after = EscrowState::fetch(self.context, &self.pda).await?;
+        let failures: Vec<String> = self
+            .checks
+            .iter()
+            .filter_map(|check| self.failure(check, &after))
+            .collect();
+        if failures.is_empty() {
+            return Ok(after);
+        }
+
+        let mut message = format!("Escrow {} expectations failed:", self.pda);
+        for failure in &failures {
+            message.push_str(&format!("\n  - {}", failure));
+        }
+        if let Some(before) = &self.before {
+            message.push_str(&format!(
+                "\nBefore: {}",
+                serde_json::to_string_pretty(before)?
+            ));
+        }
+        message.push_str(&format!(
+            "\nAfter: {}",
+            serde_json::to_string_pretty(&after)?
+        ));
+        Err(message.into())
+    }
+
+    fn failure(&self, check: &Check, after: &EscrowState) -> Option<String> {
+        match check {
+            Check::Closed => after
+                .account
+                .is_some()
+                .then(|| "expected the account to be closed".into()),
+            Check::Field(check) => match &after.account {
+                Some(account) => check(account),
+                None => Some("account is closed".into()),
+            },
+            Check::LamportsDelta {
+                expected,
+                tolerance,
+            } => {
+                let Some(before) = &self.before else {
+                    return Some("lamports_delta needs a state to compare to, see since()".into());
+                };
+                let actual = after.lamports as i64 - before.lamports as i64;
+                (actual.abs_diff(*expected) > *tolerance).then(|| {
+                    format!(
+                        "lamports changed by {} SOL, expected {} SOL{}",
+                        format_sol(actual),
+                        format_sol(*expected),
+                        balances::format_tolerance(*tolerance)
+                    )
+                })
+            }
+        }
+    }
+}
+
+fn mismatch<T: PartialEq + std::fmt::Display>(
+    field: &str,
+    expected: &T,
+    actual: &T,
+) -> Option<String> {
+    (expected != actual).then(|| format!("{}: expected {}, found {}", field, expected, actual))
+}
@@ -0,0 +1,335 @@
+//! Runs the escrow flows described by scenario files against the cluster.
+
+use super::{
+    expect::{expect, lamports_from_sol},
+    TestContext,
+};
+use crate::{
+    balances::BalanceTracker,
+    escrow::{self, MakePaymentArgs, StartSubscriptionArgs, WithdrawFundsArgs},
+    scenario::{Scenario, Step},
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// A scenario's keypairs and escrows, with the fees each actor has paid so far.
+struct Run<'a> {
+    context: &'a TestContext,
+    scenario: &'a Scenario,
+    actors: BTreeMap<String, Keypair>,
+    subscription_ids: BTreeMap<String, String>,
+    escrows: BTreeMap<String, Pubkey>,
+    tracker: BalanceTracker,
+    fees: HashMap<String, u64>,
+    /// Fees paid by each actor when each snapshot was taken
+    fees_at: HashMap<String, HashMap<String, u64>>,
+    /// Rent held by each escrow when each snapshot was taken
+    rent_at: HashMap<String, HashMap<String, u64>>,
+}
+
+pub async fn run_scenario(
+    context: &TestContext,
+    scenario: &Scenario,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("\n=== Scenario: {} ===", scenario.name);
+    if !scenario.description.is_empty() {
+        println!("{}", scenario.description.trim());
+    }
+
+    let actors: BTreeMap<String, Keypair> = scenario
+        .actors
+        .keys()
+        .map(|name| {
+            let keypair = context.keypair(&format!("{}/{}", scenario.name, name));
+            (name.clone(), keypair)
+        })
+        .collect();
+    // Subscriptions without an explicit ID get one derived from the seed
+    let subscription_ids: BTreeMap<String, String> = scenario
+        .subscriptions
+        .iter()
+        .map(|(name, subscription)| {
+            let id = subscription
+                .id
+                .clone()
+                .unwrap_or_else(|| context.subscription_id(name));
+            (name.clone(), id)
+        })
+        .collect();
+    let escrows: BTreeMap<String, Pubkey> = scenario
+        .subscriptions
+        .iter()
+        .map(|(name, subscription)| {
+            let (pda, _) = escrow::find_escrow_pda(
+                &context.program_id,
+                &actors[&subscription.buyer].pubkey(),
+                &actors[&subscription.seller].pubkey(),
+                &subscription_ids[name],
+            );
+            (name.clone(), pda)
+        })
+        .collect();
+    let tracker = BalanceTracker::new(
+        actors
+            .iter()
+            .map(|(name, keypair)| (name.as_str(), keypair.pubkey()))
+            .chain(escrows.iter().map(|(name, pda)| (name.as_str(), *pda))),
+    );
+
+    for (name, actor) in &scenario.actors {
+        if actor.airdrop_sol > 0.0 {
+            println!("\nFunding {}...", name);
+            context
+                .request_airdrop_with_confirmation(
+                    &actors[name].pubkey(),
+                    lamports_from_sol(actor.airdrop_sol) as u64,
+                )
+                .await?;
+        }
+    }
+
+    let mut run = Run {
+        context,
+        scenario,
+        actors,
+        subscription_ids,
+        escrows,
+        tracker,
+        fees: HashMap::new(),
+        fees_at: HashMap::new(),
+        rent_at: HashMap::new(),
+    };
+    for (number, step) in scenario.steps.iter().enumerate() {
+        run.step(step)
+            .await
+            .map_err(|e| format!("{}, step {}: {}", scenario.name, number + 1, e))?;
+    }
+
+    println!("\n✅ Scenario {} passed", scenario.name);
+    Ok(())
+}
+
+impl<'a> Run<'a> {
+    async fn step(&mut self, step: &Step) -> Result<(), Box<dyn std::error::Error>> {
+        match step {
+            Step::Start {
+                subscription,
+                expect_error,
+            } => {
+                println!("\nStarting {}...", subscription);
+                let (pda, buyer, seller) = self.parties(subscription);
+                let args = StartSubscriptionArgs {
+                    subscription_id: self.subscription_ids[subscription].clone(),
+                    validation_threshold: self.scenario.subscriptions[subscription]
+                        .validation_threshold,
+                };
+                let result = self
+                    .context
+                    .start_subscription(
+                        &pda,
+                        &self.actors[buyer],
+                        &self.actors[seller].pubkey(),
+                        args,
+                    )
+                    .await;
+                self.outcome("start_subscription", buyer, result, expect_error)
+            }
+            Step::Pay {
+                subscription,
+                amount_sol,
+                times,
+                expect_error,
+            } => {
+                let (pda, buyer, seller) = self.parties(subscription);
+                for i in 0..*times {
+                    println!(
+                        "\nPaying {} SOL into {} ({} of {})...",
+                        amount_sol,
+                        subscription,
+                        i + 1,
+                        times
+                    );
+                    let args = MakePaymentArgs {
+                        amount: lamports_from_sol(*amount_sol) as u64,
+                    };
+                    let result = self
+                        .context
+                        .make_payment(
+                            &pda,
+                            &self.actors[buyer],
+                            &self.actors[seller].pubkey(),
+                            args,
+                        )
+                        .await;
+                    self.outcome("make_payment", buyer, result, expect_error)?;
+                }
+                Ok(())
+            }
+            Step::Cancel {
+                subscription,
+                expect_error,
+            } => {
+                println!("\nCancelling {}...", subscription);
+                let (pda, buyer, seller) = self.parties(subscription);
+                let result = self
+                    .context
+                    .cancel_subscription(&pda, &self.actors[buyer], &self.actors[seller].pubkey())
+                    .await;
+                self.outcome("cancel_subscription", buyer, result, expect_error)
+            }
+            Step::Withdraw {
+                subscription,
+                validation_data,
+                expect_error,
+            } => {
+                println!(
+                    "\nWithdrawing from {} with validation data {}...",
+                    subscription, validation_data
+                );
+                let (pda, buyer, seller) = self.parties(subscription);
+                let args = WithdrawFundsArgs {
+                    validation_data: *validation_data,
+                };
+                let result = self
+                    .context
+                    .withdraw_funds(
+                        &pda,
+                        &self.actors[seller],
+                        &self.actors[buyer].pubkey(),
+                        args,
+                    )
+                    .await;
+                self.outcome("withdraw_funds", seller, result, expect_error)
+            }
+            Step::Snapshot { label } => {
+                let snapshot = self.tracker.snapshot(&self.context.client, label)?;
+                snapshot.print();
+                self.context.report.borrow_mut().record_balances(snapshot);
+                self.fees_at.insert(label.clone(), self.fees.clone());
+                let rent = self.escrow_rent()?;
+                self.rent_at.insert(label.clone(), rent);
+                Ok(())
+            }
+            Step::ExpectState {
+                subscription,
+                closed,
+                is_active,
+                payment_count,
+                total_amount_sol,
+            } => {
+                let mut expectation = expect(self.context).escrow(&self.escrows[subscription]);
+                if *closed == Some(true) {
+                    expectation = expectation.closed();
+                }
+                if let Some(is_active) = is_active {
+                    expectation = expectation.is_active(*is_active);
+                }
+                if let Some(payment_count) = payment_count {
+                    expectation = expectation.payment_count(*payment_count);
+                }
+                if let Some(total_amount_sol) = total_amount_sol {
+                    expectation =
+                        expectation.total_amount(lamports_from_sol(*total_amount_sol) as u64);
+                }
+                expectation.holds().await?;
+                println!("✅ {} is in the expected state", subscription);
+                Ok(())
+            }
+            Step::ExpectBalances {
+                since,
+                deltas,
+                tolerance_sol,
+                rent_refunds,
+            } => {
+                let label = format!("AFTER {}", since.to_uppercase());
+                let snapshot = self.tracker.snapshot(&self.context.client, &label)?;
+                self.context.report.borrow_mut().record_balances(snapshot);
+                let delta = self.tracker.delta(since, &label)?;
+                delta.print();
+                let fees_before = &self.fees_at[since];
+                let rent_before = &self.rent_at[since];
+                for (account, expected_sol) in deltas {
+                    let fees = self.fees.get(account).copied().unwrap_or_default()
+                        - fees_before.get(account).copied().unwrap_or_default();
+                    let mut expected = lamports_from_sol(*expected_sol) - fees as i64;
+                    for (subscription, refunded_to) in rent_refunds {
+                        let rent = rent_before[subscription] as i64;
+                        if account == subscription {
+                            expected -= rent;
+                        }
+                        if account == refunded_to {
+                            expected += rent;
+                        }
+                    }
+                    delta.expect(account, expected, lamports_from_sol(*tolerance_sol) as u64)?;
+                }
+                println!("✅ Balances changed as expected since {}", since);
+                Ok(())
+            }
+        }
+    }
+
+    /// Rent-exempt minimum of each escrow's current allocation, zero for
+    /// escrows that don't exist.
+    fn escrow_rent(&self) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+        let pdas: Vec<Pubkey> = self.escrows.values().copied().collect();
+        let accounts = self.context.client.get_multiple_accounts(&pdas)?;
+        let mut rent = HashMap::new();
+        for (name, account) in self.escrows.keys().zip(accounts) {
+            let lamports = match account {
+                Some(account) => self
+                    .context
+                    .client
+                    .get_minimum_balance_for_rent_exemption(account.data.len())?,
+                None => 0,
+            };
+            rent.insert(name.clone(), lamports);
+        }
+        Ok(rent)
+    }
+
+    /// The subscription's PDA and the names of its buyer and seller.
+    fn parties(&self, subscription: &str) -> (Pubkey, &'a str, &'a str) {
+        let parties = &self.scenario.subscriptions[subscription];
+        (self.escrows[subscription], &parties.buyer, &parties.seller)
+    }
+
+    /// Checks a transaction's result against the step's `expect_error`, and
+    /// charges its fee to `payer` when it went through.
+    fn outcome(
+        &mut self,
+        label: &str,
+        payer: &str,
+        result: Result<Signature, Box<dyn std::error::Error>>,
+        expect_error: &Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match (result, expect_error) {
+            (Ok(signature), None) => {
+                *self.fees.entry(payer.to_string()).or_default() +=
+                    self.context.fee_paid(&signature);
+                println!("✅ {} confirmed. Signature: {}", label, signature);
+                Ok(())
+            }
+            (Ok(signature), Some(expected)) => Err(format!(
+                "{} succeeded ({}), expected it to fail with \"{}\"",
+                label, signature, expected
+            )
+            .into()),
+            (Err(e), Some(expected)) => {
+                if !e.to_string().contains(expected.as_str()) {
+                    return Err(format!(
+                        "{} failed with \"{}\", expected an error containing \"{}\"",
+                        label, e, expected
+                    )
+                    .into());
+                }
+                println!("✅ {} failed as expected: {}", label, e);
+                Ok(())
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use crate::{
+    balances::{format_sol, format_sol_balance},
+    decoder::{self, AccountRole, EscrowInstruction},
+    submit,
+    transaction::{self, ConfirmedTransaction},
+};
+use solana_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient};
+use solana_sdk::{
+    bs58, pubkey::Pubkey, signature::Signature, system_instruction::SystemInstruction,
+    system_program,
+};
+use solana_transaction_status::{UiInnerInstructions, UiInstruction};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentDestination {
+    Escrow,
+    Direct,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawalOutcome {
+    PaidToSeller,
+    RefundedToBuyer,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EscrowEvent {
+    Started {
+        subscription_id: String,
+        validation_threshold: u64,
+    },
+    Payment {
+        amount: u64,
+        destination: PaymentDestination,
+    },
+    Cancelled,
+    Withdrawal {
+        validation_data: u64,
+        outcome: WithdrawalOutcome,
+    },
+}
+
+/// Lamports gained (positive) or lost (negative) by each party, excluding
+/// the transaction fee.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LamportMovement {
+    pub escrow: i64,
+    pub buyer: i64,
+    pub seller: i64,
+}
+
+pub struct HistoryEntry {
+    pub signature: Signature,
+    /// Position of the instruction within its transaction
+    pub instruction_index: usize,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub event: EscrowEvent,
+    pub movement: LamportMovement,
+}
+
+/// Rebuilds the history of an escrow PDA, oldest first, from every successful
+/// transaction that references it, or only those newer than `until`.
+pub fn fetch_history(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    until: Option<Signature>,
+) -> Result<Vec<HistoryEntry>, Box<dyn std::error::Error>> {
+    let mut history = Vec::new();
+    for signature in fetch_signatures(client, escrow, until)? {
+        let confirmed = transaction::fetch_confirmed_transaction(client, &signature)?;
+        history.extend(
+            transaction_entries(program_id, &signature, &confirmed)
+                .into_iter()
+                .filter(|entry| entry.escrow == *escrow),
+        );
+    }
+    Ok(history)
+}
+
+/// Signature of the newest transaction referencing `address`, successful or
+/// not, to pass as `until` so later fetches skip everything up to it.
+pub fn latest_signature(
+    client: &RpcClient,
+    address: &Pubkey,
+) -> Result<Option<Signature>, Box<dyn std::error::Error>> {
+    let config = GetConfirmedSignaturesForAddress2Config {
+        before: None,
+        until: None,
+        limit: Some(1),
+        commitment: Some(submit::history_commitment(client.commitment())),
+    };
+    let page = client.get_signatures_for_address_with_config(address, config)?;
+    page.first()
+        .map(|status| Ok(status.signature.parse()?))
+        .transpose()
+}
+
+/// Signatures of every successful transaction referencing `address`, oldest
+/// first, stopping at `until` when given.
+///
+/// Failed transactions are skipped since they changed nothing but the fee
+/// payer's balance.
+pub fn fetch_signatures(
+    client: &RpcClient,
+    address: &Pubkey,
+    until: Option<Signature>,
+) -> Result<Vec<Signature>, Box<dyn std::error::Error>> {
+    let mut statuses = Vec::new();
+    let mut before = None;
+    loop {
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before,
+            until,
+            limit: None,
+            commitment: Some(submit::history_commitment(client.commitment())),
+        };
+        let page = client.get_signatures_for_address_with_config(address, config)?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(last.signature.parse()?);
+        statuses.extend(page);
+    }
+
+    // Signatures come newest first
+    statuses
+        .into_iter()
+        .rev()
+        .filter(|status| status.err.is_none())
+        .map(|status| Ok(status.signature.parse()?))
+        .collect()
+}
+
+/// History entries for every escrow instruction in a confirmed transaction.
+///
+/// Payments are classified as escrowed or direct from the transfer each one
+/// made. When the node leaves out inner instructions, they are classified
+/// from the escrow's balance change instead, attributed in instruction order,
+/// which rent and withdrawals in the same transaction can skew.
+pub fn transaction_entries(
+    program_id: &Pubkey,
+    signature: &Signature,
+    confirmed: &ConfirmedTransaction,
+) -> Vec<HistoryEntry> {
+    let keys = &confirmed.account_keys;
+    let meta = &confirmed.meta;
+    if meta.err.is_some() {
+        return Vec::new();
+    }
+
+    // The fee payer is always the first account; add its fee back
+    let delta = |key: &Pubkey| {
+        keys.iter().position(|k| k == key).map_or(0, |index| {
+            let fee = if index == 0 { meta.fee as i64 } else { 0 };
+            meta.post_balances[index] as i64 - meta.pre_balances[index] as i64 + fee
+        })
+    };
+
+    let inner_instructions: Option<Vec<UiInnerInstructions>> =
+        meta.inner_instructions.clone().into();
+    let mut escrowed_remaining: HashMap<Pubkey, i64> = HashMap::new();
+    let mut entries = Vec::new();
+    for decoded in decoder::decode_message(program_id, &confirmed.transaction.message, keys) {
+        let (Some(&escrow), Some(&buyer), Some(&seller)) = (
+            decoded.account(AccountRole::Escrow),
+            decoded.account(AccountRole::Buyer),
+            decoded.account(AccountRole::Seller),
+        ) else {
+            continue;
+        };
+        let Ok(instruction) = decoded.instruction else {
+            continue;
+        };
+
+        let movement = LamportMovement {
+            escrow: delta(&escrow),
+            buyer: delta(&buyer),
+            seller: delta(&seller),
+        };
+        let (event, movement) = match instruction {
+            EscrowInstruction::StartSubscription(args) => (
+                EscrowEvent::Started {
+                    subscription_id: args.subscription_id,
+                    validation_threshold: args.validation_threshold,
+                },
+                movement,
+            ),
+            EscrowInstruction::MakePayment(args) => {
+                let amount = args.amount as i64;
+                let escrowed = match &inner_instructions {
+                    Some(inner) => transfers(inner, decoded.index, keys)
+                        .any(|transfer| transfer == (buyer, escrow, args.amount)),
+                    None => {
+                        let remaining = escrowed_remaining.entry(escrow).or_insert(movement.escrow);
+                        let escrowed = *remaining >= amount;
+                        if escrowed {
+                            *remaining -= amount;
+                        }
+                        escrowed
+                    }
+                };
+                if escrowed {
+                    (
+                        EscrowEvent::Payment {
+                            amount: args.amount,
+                            destination: PaymentDestination::Escrow,
+                        },
+                        LamportMovement {
+                            escrow: amount,
+                            buyer: -amount,
+                            seller: 0,
+                        },
+                    )
+                } else {
+                    (
+                        EscrowEvent::Payment {
+                            amount: args.amount,
+                            destination: PaymentDestination::Direct,
+                        },
+                        LamportMovement {
+                            escrow: 0,
+                            buyer: -amount,
+                            seller: amount,
+                        },
+                    )
+                }
+            }
+            EscrowInstruction::CancelSubscription => (EscrowEvent::Cancelled, movement),
+            EscrowInstruction::WithdrawFunds(args) => {
+                let outcome = if movement.seller > 0 {
+                    WithdrawalOutcome::PaidToSeller
+                } else {
+                    WithdrawalOutcome::RefundedToBuyer
+                };
+                (
+                    EscrowEvent::Withdrawal {
+                        validation_data: args.validation_data,
+                        outcome,
+                    },
+                    movement,
+                )
+            }
+        };
+
+        entries.push(HistoryEntry {
+            signature: *signature,
+            instruction_index: decoded.index,
+            slot: confirmed.slot,
+            block_time: confirmed.block_time,
+            escrow,
+            buyer,
+            seller,
+            event,
+            movement,
+        });
+    }
+    entries
+}
+
+/// System Program transfers made by the top-level instruction at `index`, as
+/// source, destination and lamports.
+fn transfers<'a>(
+    inner_instructions: &'a [UiInnerInstructions],
+    index: usize,
+    keys: &'a [Pubkey],
+) -> impl Iterator<Item = (Pubkey, Pubkey, u64)> + 'a {
+    inner_instructions
+        .iter()
+        .filter(move |inner| usize::from(inner.index) == index)
+        .flat_map(|inner| &inner.instructions)
+        .filter_map(move |instruction| {
+            let UiInstruction::Compiled(instruction) = instruction else {
+                return None;
+            };
+            let key = |position: usize| keys.get(usize::from(*instruction.accounts.get(position)?));
+            if keys.get(usize::from(instruction.program_id_index)) != Some(&system_program::id()) {
+                return None;
+            }
+            let data = bs58::decode(&instruction.data).into_vec().ok()?;
+            match bincode::deserialize(&data).ok()? {
+                SystemInstruction::Transfer { lamports } => Some((*key(0)?, *key(1)?, lamports)),
+                _ => None,
+            }
+        })
+}
+
+pub fn print_history(escrow: &Pubkey, history: &[HistoryEntry]) {
+    println!("\n=== History of escrow {} ===", escrow);
+    for entry in history {
+        let description = match &entry.event {
+            EscrowEvent::Started {
+                subscription_id,
+                validation_threshold,
+            } => format!(
+                "Started {} (validation threshold {})",
+                subscription_id, validation_threshold
+            ),
+            EscrowEvent::Payment {
+                amount,
+                destination,
+            } => format!(
+                "Payment of {} SOL ({})",
+                format_sol_balance(*amount),
+                match destination {
+                    PaymentDestination::Escrow => "escrowed",
+                    PaymentDestination::Direct => "direct to seller",
+                }
+            ),
+            EscrowEvent::Cancelled => "Cancelled".to_string(),
+            EscrowEvent::Withdrawal {
+                validation_data,
+                outcome,
+            } => format!(
+                "Withdrawal with validation data {} ({})",
+                validation_data,
+                match outcome {
+                    WithdrawalOutcome::PaidToSeller => "paid to seller",
+                    WithdrawalOutcome::RefundedToBuyer => "refunded to buyer",
+                }
+            ),
+        };
+        println!("[slot {}] {}", entry.slot, description);
+        println!("  Signature: {}", entry.signature);
+        if let Some(block_time) = entry.block_time {
+            println!("  Block time: {}", block_time);
+        }
+        println!(
+            "  Escrow: {} SOL, Buyer: {} SOL, Seller: {} SOL",
+            format_sol(entry.movement.escrow),
+            format_sol(entry.movement.buyer),
+            format_sol(entry.movement.seller)
+        );
+    }
+    println!("========================\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escrow::{self, MakePaymentArgs, StartSubscriptionArgs};
+    use solana_sdk::{
+        message::{Message, VersionedMessage},
+        transaction::VersionedTransaction,
+    };
+
+    const RENT: u64 = 2_000_000;
+    const AMOUNT: u64 = 1_000_000;
+    const FEE: u64 = 5000;
+
+    #[test]
+    fn classifies_payments_by_their_own_transfer() {
+        let program_id = crate::ID;
+        let buyer = Pubkey::new_from_array([2; 32]);
+        let seller = Pubkey::new_from_array([1; 32]);
+        let escrow = Pubkey::new_from_array([3; 32]);
+        let start = escrow::start_subscription(
+            &program_id,
+            &escrow,
+            &buyer,
+            &seller,
+            &StartSubscriptionArgs {
+                subscription_id: "premium_content".to_string(),
+                validation_threshold: escrow::DEFAULT_VALIDATION_THRESHOLD,
+            },
+        )
+        .unwrap();
+        let payment = escrow::make_payment(
+            &program_id,
+            &escrow,
+            &buyer,
+            &seller,
+            &MakePaymentArgs { amount: AMOUNT },
+        )
+        .unwrap();
+        let message = Message::new(&[start, payment], Some(&buyer));
+        let keys = message.account_keys.clone();
+        let index = |key: &Pubkey| keys.iter().position(|k| k == key).unwrap();
+        let system = index(&system_program::id());
+
+        // The escrow gains more rent than the payment, which went to the seller
+        let mut pre_balances = vec![0; keys.len()];
+        pre_balances[index(&buyer)] = 10 * RENT;
+        let mut post_balances = pre_balances.clone();
+        post_balances[index(&buyer)] -= RENT + AMOUNT + FEE;
+        post_balances[index(&escrow)] += RENT;
+        post_balances[index(&seller)] += AMOUNT;
+        let compiled = |instruction: SystemInstruction, from: &Pubkey, to: &Pubkey| {
+            serde_json::json!({
+                "programIdIndex": system,
+                "accounts": [index(from), index(to)],
+                "data": bs58::encode(bincode::serialize(&instruction).unwrap()).into_string(),
+                "stackHeight": 2,
+            })
+        };
+        let meta = serde_json::from_value(serde_json::json!({
+            "err": null,
+            "status": { "Ok": null },
+            "fee": FEE,
+            "preBalances": pre_balances,
+            "postBalances": post_balances,
+            "innerInstructions": [
+                {
+                    "index": 0,
+                    "instructions": [compiled(
+                        SystemInstruction::CreateAccount {
+                            lamports: RENT,
+                            space: 126,
+                            owner: program_id,
+                        },
+                        &buyer,
+                        &escrow,
+                    )],
+                },
+                {
+                    "index": 1,
+                    "instructions": [compiled(
+                        SystemInstruction::Transfer { lamports: AMOUNT },
+                        &buyer,
+                        &seller,
+                    )],
+                },
+            ],
+        }))
+        .unwrap();
+        let confirmed = ConfirmedTransaction {
+            slot: 7,
+            block_time: None,
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::default()],
+                message: VersionedMessage::Legacy(message),
+            },
+            account_keys: keys.clone(),
+            meta,
+        };
+
+        let entries = transaction_entries(&program_id, &Signature::default(), &confirmed);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1].event,
+            EscrowEvent::Payment {
+                amount: AMOUNT,
+                destination: PaymentDestination::Direct,
+            }
+        );
+    }
+}
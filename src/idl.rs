@@ -0,0 +1,404 @@
+use crate::{
+    decoder::ACCOUNT_ROLES,
+    escrow::{
+        self, get_instruction_sighash, EscrowAccount, MakePaymentArgs, StartSubscriptionArgs,
+        WithdrawFundsArgs,
+    },
+};
+use anchor_lang::{AnchorDeserialize, AnchorSerialize, Discriminator};
+use anchor_lang_idl_spec::{
+    Idl, IdlDefinedFields, IdlField, IdlInstructionAccount, IdlInstructionAccountItem, IdlPda,
+    IdlSeed, IdlType, IdlTypeDefTy,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+use std::path::Path;
+
+/// Anchor IDL the escrow client types are generated from by build.rs.
+pub const IDL_JSON: &str = include_str!("../idl/subscription_escrow.json");
+
+/// Loads an IDL file, such as `target/idl/subscription_escrow.json` from an
+/// `anchor build`, or the bundled IDL when no path is given.
+pub fn load(path: Option<&Path>) -> Result<Idl, Box<dyn std::error::Error>> {
+    let json = match path {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => IDL_JSON.to_string(),
+    };
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Field names paired with their values, nested struct fields flattened as `outer.inner`.
+type Fields = Vec<(String, String)>;
+
+fn field(name: &str, value: impl ToString) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+/// An instruction built by the client together with the arguments it encodes.
+struct InstructionSample {
+    name: &'static str,
+    instruction: Instruction,
+    args: Fields,
+}
+
+/// Checks the client against `idl`: instruction discriminators, account order
+/// and flags, escrow PDA seeds, and the Borsh layouts of the argument structs
+/// and `EscrowAccount`.
+///
+/// Layouts are compared by serializing sample values with the client's types
+/// and decoding them field by field as the IDL describes, so a reordered,
+/// renamed, retyped, missing or extra field is reported.
+pub fn check_client_types(idl: &Idl) -> Result<(), Box<dyn std::error::Error>> {
+    let program_id = crate::ID;
+    if idl.address != program_id.to_string() {
+        return Err(format!(
+            "IDL address {} differs from the client's program ID {}",
+            idl.address, program_id
+        )
+        .into());
+    }
+
+    let samples = instruction_samples(&program_id)?;
+    for idl_instruction in &idl.instructions {
+        if !samples
+            .iter()
+            .any(|sample| sample.name == idl_instruction.name)
+        {
+            return Err(format!(
+                "IDL instruction {} has no client builder",
+                idl_instruction.name
+            )
+            .into());
+        }
+    }
+    for sample in &samples {
+        check_instruction(idl, &program_id, sample)?;
+        println!("✅ {} matches the IDL", sample.name);
+    }
+
+    check_escrow_account(idl)?;
+    println!("✅ EscrowAccount matches the IDL");
+    Ok(())
+}
+
+fn instruction_samples(
+    program_id: &Pubkey,
+) -> Result<Vec<InstructionSample>, Box<dyn std::error::Error>> {
+    let buyer = Pubkey::new_unique();
+    let seller = Pubkey::new_unique();
+    let subscription_id = "idl_check";
+    let (escrow, _) = escrow::find_escrow_pda(program_id, &buyer, &seller, subscription_id);
+
+    // Distinct values so that swapped fields of the same type are caught
+    let validation_threshold = 1_001;
+    let amount = 2_002;
+    let validation_data = 3_003;
+
+    Ok(vec![
+        InstructionSample {
+            name: "start_subscription",
+            instruction: escrow::start_subscription(
+                program_id,
+                &escrow,
+                &buyer,
+                &seller,
+                &StartSubscriptionArgs {
+                    subscription_id: subscription_id.to_string(),
+                    validation_threshold,
+                },
+            )?,
+            args: vec![
+                field("subscription_id", subscription_id),
+                field("validation_threshold", validation_threshold),
+            ],
+        },
+        InstructionSample {
+            name: "make_payment",
+            instruction: escrow::make_payment(
+                program_id,
+                &escrow,
+                &buyer,
+                &seller,
+                &MakePaymentArgs { amount },
+            )?,
+            args: vec![field("amount", amount)],
+        },
+        InstructionSample {
+            name: "cancel_subscription",
+            instruction: escrow::cancel_subscription(program_id, &escrow, &buyer, &seller),
+            args: Vec::new(),
+        },
+        InstructionSample {
+            name: "withdraw_funds",
+            instruction: escrow::withdraw_funds(
+                program_id,
+                &escrow,
+                &buyer,
+                &seller,
+                &WithdrawFundsArgs { validation_data },
+            )?,
+            args: vec![field("validation_data", validation_data)],
+        },
+    ])
+}
+
+fn check_instruction(
+    idl: &Idl,
+    program_id: &Pubkey,
+    sample: &InstructionSample,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let name = sample.name;
+    let idl_instruction = idl
+        .instructions
+        .iter()
+        .find(|instruction| instruction.name == name)
+        .ok_or_else(|| format!("IDL has no instruction {}", name))?;
+
+    let sighash = get_instruction_sighash(name);
+    if idl_instruction.discriminator != sighash {
+        return Err(format!(
+            "{} discriminator is {:?} in the IDL but {:?} in the client",
+            name, idl_instruction.discriminator, sighash
+        )
+        .into());
+    }
+    if sample.instruction.data.get(..8) != Some(&sighash[..]) {
+        return Err(format!("{} data does not start with its sighash", name).into());
+    }
+
+    let idl_accounts = idl_instruction
+        .accounts
+        .iter()
+        .map(|item| match item {
+            IdlInstructionAccountItem::Single(account) => Ok(account),
+            IdlInstructionAccountItem::Composite(accounts) => Err(format!(
+                "{} uses composite accounts {}, which the client does not build",
+                name, accounts.name
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let metas = &sample.instruction.accounts;
+    if idl_accounts.len() != metas.len() {
+        return Err(format!(
+            "{} takes {} accounts in the IDL but {} in the client",
+            name,
+            idl_accounts.len(),
+            metas.len()
+        )
+        .into());
+    }
+    for ((idl_account, meta), role) in idl_accounts.iter().zip(metas).zip(ACCOUNT_ROLES) {
+        let role = role.to_string();
+        if idl_account.name != role {
+            return Err(format!(
+                "{} expects {} where the client passes {}",
+                name, idl_account.name, role
+            )
+            .into());
+        }
+        if idl_account.writable != meta.is_writable || idl_account.signer != meta.is_signer {
+            return Err(format!(
+                "{} account {} is writable={}, signer={} in the IDL but writable={}, signer={} in the client",
+                name,
+                role,
+                idl_account.writable,
+                idl_account.signer,
+                meta.is_writable,
+                meta.is_signer
+            )
+            .into());
+        }
+        if let Some(address) = &idl_account.address {
+            if *address != meta.pubkey.to_string() {
+                return Err(format!(
+                    "{} account {} must be {} but the client passes {}",
+                    name, role, address, meta.pubkey
+                )
+                .into());
+            }
+        }
+        if let Some(pda) = &idl_account.pda {
+            let derived = derive_pda(pda, program_id, &idl_accounts, metas, sample)?;
+            if derived != meta.pubkey {
+                return Err(format!(
+                    "{} account {} derives to {} from the IDL seeds but the client passes {}",
+                    name, role, derived, meta.pubkey
+                )
+                .into());
+            }
+        }
+    }
+
+    check_layout(
+        idl,
+        &format!("{} arguments", name),
+        &idl_instruction.args,
+        &sample.instruction.data[8..],
+        &sample.args,
+    )
+}
+
+/// Derives a PDA from IDL seeds. Only string arguments are supported as seeds,
+/// which covers the escrow's subscription ID.
+fn derive_pda(
+    pda: &IdlPda,
+    program_id: &Pubkey,
+    idl_accounts: &[&IdlInstructionAccount],
+    metas: &[AccountMeta],
+    sample: &InstructionSample,
+) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let mut seeds: Vec<Vec<u8>> = Vec::with_capacity(pda.seeds.len());
+    for seed in &pda.seeds {
+        let seed = match seed {
+            IdlSeed::Const(seed) => seed.value.clone(),
+            IdlSeed::Account(seed) => {
+                let position = idl_accounts
+                    .iter()
+                    .position(|account| account.name == seed.path)
+                    .ok_or_else(|| format!("PDA seed references unknown account {}", seed.path))?;
+                metas[position].pubkey.to_bytes().to_vec()
+            }
+            IdlSeed::Arg(seed) => sample
+                .args
+                .iter()
+                .find(|(name, _)| *name == seed.path)
+                .map(|(_, value)| value.as_bytes().to_vec())
+                .ok_or_else(|| format!("PDA seed references unknown argument {}", seed.path))?,
+        };
+        seeds.push(seed);
+    }
+    if pda.program.is_some() {
+        return Err("PDAs of other programs are not supported".into());
+    }
+    let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+    Ok(Pubkey::find_program_address(&seeds, program_id).0)
+}
+
+fn check_escrow_account(idl: &Idl) -> Result<(), Box<dyn std::error::Error>> {
+    let idl_account = idl
+        .accounts
+        .iter()
+        .find(|account| account.name == "EscrowAccount")
+        .ok_or("IDL has no EscrowAccount account")?;
+    if idl_account.discriminator != EscrowAccount::DISCRIMINATOR {
+        return Err(format!(
+            "EscrowAccount discriminator is {:?} in the IDL but {:?} in the client",
+            idl_account.discriminator,
+            EscrowAccount::DISCRIMINATOR
+        )
+        .into());
+    }
+
+    let sample = EscrowAccount {
+        seller: Pubkey::new_unique(),
+        buyer: Pubkey::new_unique(),
+        subscription_id: "idl_check".to_string(),
+        payment_count: 7,
+        total_amount: 4_004,
+        is_active: true,
+        validation_threshold: 5_005,
+    };
+    let expected = vec![
+        field("seller", sample.seller),
+        field("buyer", sample.buyer),
+        field("subscription_id", &sample.subscription_id),
+        field("payment_count", sample.payment_count),
+        field("total_amount", sample.total_amount),
+        field("is_active", sample.is_active),
+        field("validation_threshold", sample.validation_threshold),
+    ];
+    check_layout(
+        idl,
+        "EscrowAccount fields",
+        struct_fields(idl, "EscrowAccount")?,
+        &sample.try_to_vec()?,
+        &expected,
+    )
+}
+
+/// Decodes `data` as the IDL describes `fields` and compares the result with
+/// the values the client serialized.
+fn check_layout(
+    idl: &Idl,
+    what: &str,
+    fields: &[IdlField],
+    mut data: &[u8],
+    expected: &Fields,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut decoded = Vec::new();
+    decode_fields(idl, fields, &mut data, "", &mut decoded)
+        .map_err(|e| format!("{} do not match the IDL layout: {}", what, e))?;
+    if !data.is_empty() {
+        return Err(format!(
+            "{} have {} trailing byte(s) not described by the IDL",
+            what,
+            data.len()
+        )
+        .into());
+    }
+    if decoded != *expected {
+        return Err(format!(
+            "{} differ from the IDL\n  client: {:?}\n  IDL:    {:?}",
+            what, expected, decoded
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Borsh-decodes each field in turn, recursing into defined structs. Only the
+/// types the escrow program uses are supported.
+fn decode_fields(
+    idl: &Idl,
+    fields: &[IdlField],
+    data: &mut &[u8],
+    prefix: &str,
+    decoded: &mut Fields,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for idl_field in fields {
+        let name = format!("{}{}", prefix, idl_field.name);
+        let value = match &idl_field.ty {
+            IdlType::Bool => bool::deserialize(data)?.to_string(),
+            IdlType::U8 => u8::deserialize(data)?.to_string(),
+            IdlType::U64 => u64::deserialize(data)?.to_string(),
+            IdlType::String => String::deserialize(data)?,
+            IdlType::Pubkey => Pubkey::deserialize(data)?.to_string(),
+            IdlType::Defined {
+                name: type_name, ..
+            } => {
+                let nested = struct_fields(idl, type_name)?;
+                decode_fields(idl, nested, data, &format!("{}.", name), decoded)?;
+                continue;
+            }
+            other => return Err(format!("unsupported type {:?} for {}", other, name).into()),
+        };
+        decoded.push((name, value));
+    }
+    Ok(())
+}
+
+fn struct_fields<'a>(
+    idl: &'a Idl,
+    name: &str,
+) -> Result<&'a [IdlField], Box<dyn std::error::Error>> {
+    match idl.types.iter().find(|ty| ty.name == name).map(|ty| &ty.ty) {
+        Some(IdlTypeDefTy::Struct {
+            fields: Some(IdlDefinedFields::Named(fields)),
+        }) => Ok(fields),
+        Some(IdlTypeDefTy::Struct { fields: None }) => Ok(&[]),
+        Some(_) => Err(format!("IDL type {} is not a struct with named fields", name).into()),
+        None => Err(format!("IDL has no type {}", name).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_types_match_bundled_idl() {
+        check_client_types(&load(None).unwrap()).unwrap();
+    }
+}
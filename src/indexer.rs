@@ -0,0 +1,482 @@
+use crate::{
+    balances::format_sol_balance,
+    history::{self, EscrowEvent, HistoryEntry, PaymentDestination, WithdrawalOutcome},
+    transaction,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use solana_client::{
+    pubsub_client::PubsubClient,
+    rpc_client::RpcClient,
+    rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{collections::HashSet, path::Path, time::Duration};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sync_cursors (
+    address TEXT PRIMARY KEY,
+    signature TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS indexed_instructions (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    PRIMARY KEY (signature, instruction_index)
+);
+CREATE TABLE IF NOT EXISTS subscriptions (
+    escrow TEXT PRIMARY KEY,
+    subscription_id TEXT NOT NULL,
+    buyer TEXT NOT NULL,
+    seller TEXT NOT NULL,
+    validation_threshold INTEGER NOT NULL,
+    payment_count INTEGER NOT NULL DEFAULT 0,
+    total_amount INTEGER NOT NULL DEFAULT 0,
+    is_active INTEGER NOT NULL DEFAULT 1,
+    closed INTEGER NOT NULL DEFAULT 0,
+    started_slot INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS subscriptions_by_seller ON subscriptions (seller);
+CREATE TABLE IF NOT EXISTS payments (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    escrow TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    destination TEXT NOT NULL,
+    PRIMARY KEY (signature, instruction_index)
+);
+CREATE TABLE IF NOT EXISTS withdrawals (
+    signature TEXT NOT NULL,
+    instruction_index INTEGER NOT NULL,
+    escrow TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    validation_data INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    seller_amount INTEGER NOT NULL,
+    buyer_amount INTEGER NOT NULL,
+    PRIMARY KEY (signature, instruction_index)
+);
+";
+
+/// A subscription as reconstructed from indexed transactions.
+pub struct IndexedSubscription {
+    pub escrow: Pubkey,
+    pub subscription_id: String,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub payment_count: u64,
+    pub total_amount: u64,
+    pub is_active: bool,
+    pub closed: bool,
+}
+
+/// Funds returned to a buyer by a withdrawal that failed validation.
+pub struct Refund {
+    pub signature: Signature,
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub slot: u64,
+    pub amount: u64,
+}
+
+/// Applies escrow transactions to a local SQLite copy of the program's
+/// subscriptions, payments and withdrawals.
+///
+/// Every instruction is recorded by signature and position, so replaying a
+/// backfill or overlapping it with a live feed never counts an event twice.
+pub struct Indexer {
+    connection: Connection,
+}
+
+impl Indexer {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+
+    /// Applies a history entry, returning `false` if it was already indexed.
+    pub fn apply(&mut self, entry: &HistoryEntry) -> Result<bool, Box<dyn std::error::Error>> {
+        let tx = self.connection.transaction()?;
+        let signature = entry.signature.to_string();
+        let escrow = entry.escrow.to_string();
+        let index = entry.instruction_index as i64;
+        let slot = entry.slot as i64;
+
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO indexed_instructions (signature, instruction_index)
+             VALUES (?1, ?2)",
+            params![signature, index],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        match &entry.event {
+            EscrowEvent::Started {
+                subscription_id,
+                validation_threshold,
+            } => {
+                tx.execute(
+                    "INSERT OR REPLACE INTO subscriptions
+                     (escrow, subscription_id, buyer, seller, validation_threshold, started_slot)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        escrow,
+                        subscription_id,
+                        entry.buyer.to_string(),
+                        entry.seller.to_string(),
+                        *validation_threshold as i64,
+                        slot
+                    ],
+                )?;
+            }
+            EscrowEvent::Payment {
+                amount,
+                destination,
+            } => {
+                let (destination, escrowed) = match destination {
+                    PaymentDestination::Escrow => ("escrow", *amount),
+                    PaymentDestination::Direct => ("direct", 0),
+                };
+                tx.execute(
+                    "INSERT INTO payments
+                     (signature, instruction_index, escrow, slot, amount, destination)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![signature, index, escrow, slot, *amount as i64, destination],
+                )?;
+                tx.execute(
+                    "UPDATE subscriptions
+                     SET payment_count = payment_count + 1, total_amount = total_amount + ?2
+                     WHERE escrow = ?1",
+                    params![escrow, escrowed as i64],
+                )?;
+            }
+            EscrowEvent::Cancelled => {
+                tx.execute(
+                    "UPDATE subscriptions SET is_active = 0 WHERE escrow = ?1",
+                    params![escrow],
+                )?;
+            }
+            EscrowEvent::Withdrawal {
+                validation_data,
+                outcome,
+            } => {
+                let outcome = match outcome {
+                    WithdrawalOutcome::PaidToSeller => "paid_to_seller",
+                    WithdrawalOutcome::RefundedToBuyer => "refunded_to_buyer",
+                };
+                tx.execute(
+                    "INSERT INTO withdrawals
+                     (signature, instruction_index, escrow, slot, validation_data, outcome,
+                      seller_amount, buyer_amount)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        signature,
+                        index,
+                        escrow,
+                        slot,
+                        *validation_data as i64,
+                        outcome,
+                        entry.movement.seller.max(0),
+                        entry.movement.buyer.max(0)
+                    ],
+                )?;
+                tx.execute(
+                    "UPDATE subscriptions SET is_active = 0, closed = 1 WHERE escrow = ?1",
+                    params![escrow],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Indexes every successful escrow transaction that references `address`,
+    /// either the program ID for a full backfill or a single escrow PDA, and
+    /// is newer than `until` when given. Returns the number of newly indexed
+    /// instructions.
+    pub fn backfill(
+        &mut self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        address: &Pubkey,
+        until: Option<Signature>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut indexed = 0;
+        for signature in history::fetch_signatures(client, address, until)? {
+            indexed += self.index_transaction(client, program_id, &signature)?;
+        }
+        Ok(indexed)
+    }
+
+    /// Backfills `address` from the newest signature an earlier sync of it
+    /// indexed, and records the new newest one. Returns the number of newly
+    /// indexed instructions.
+    pub fn sync(
+        &mut self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        address: &Pubkey,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(self.sync_signatures(client, program_id, address)?.0)
+    }
+
+    /// `sync`, also returning the signatures it indexed.
+    fn sync_signatures(
+        &mut self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        address: &Pubkey,
+    ) -> Result<(usize, HashSet<Signature>), Box<dyn std::error::Error>> {
+        let cursor: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT signature FROM sync_cursors WHERE address = ?1",
+                params![address.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let until = cursor.map(|signature| signature.parse()).transpose()?;
+
+        let signatures = history::fetch_signatures(client, address, until)?;
+        let mut indexed = 0;
+        for signature in &signatures {
+            indexed += self.index_transaction(client, program_id, signature)?;
+        }
+        if let Some(newest) = signatures.last() {
+            self.connection.execute(
+                "INSERT OR REPLACE INTO sync_cursors (address, signature) VALUES (?1, ?2)",
+                params![address.to_string(), newest.to_string()],
+            )?;
+        }
+        Ok((indexed, signatures.into_iter().collect()))
+    }
+
+    /// Syncs `address`, then indexes escrow transactions as they are
+    /// confirmed, until the websocket closes.
+    ///
+    /// The subscription is opened before the backfill, so transactions that
+    /// land during it wait in the subscription's channel instead of being
+    /// missed. Those the backfill already indexed are skipped.
+    pub fn follow(
+        &mut self,
+        client: &RpcClient,
+        ws_url: &str,
+        program_id: &Pubkey,
+        address: &Pubkey,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (_subscription, receiver) = PubsubClient::logs_subscribe(
+            ws_url,
+            RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+            RpcTransactionLogsConfig {
+                commitment: Some(client.commitment()),
+            },
+        )?;
+        let (indexed, backfilled) = self.sync_signatures(client, program_id, address)?;
+        println!("Indexed {} instruction(s) from history", indexed);
+
+        println!("Following new transactions (Ctrl-C to stop)");
+        while let Ok(response) = receiver.recv() {
+            if response.value.err.is_some() {
+                continue;
+            }
+            let signature: Signature = response.value.signature.parse()?;
+            if backfilled.contains(&signature) {
+                continue;
+            }
+            let indexed = self.index_transaction(client, program_id, &signature)?;
+            println!("Indexed {} instruction(s) from {}", indexed, signature);
+        }
+        Ok(())
+    }
+
+    fn index_transaction(
+        &mut self,
+        client: &RpcClient,
+        program_id: &Pubkey,
+        signature: &Signature,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        // A transaction announced over the websocket may not be served by
+        // getTransaction for a moment
+        let mut attempts = 0;
+        let confirmed = loop {
+            match transaction::fetch_confirmed_transaction(client, signature) {
+                Ok(confirmed) => break confirmed,
+                Err(_) if attempts < 10 => {
+                    attempts += 1;
+                    std::thread::sleep(Duration::from_millis(500));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut indexed = 0;
+        for entry in history::transaction_entries(program_id, signature, &confirmed) {
+            if self.apply(&entry)? {
+                indexed += 1;
+            }
+        }
+        Ok(indexed)
+    }
+
+    pub fn subscription(
+        &self,
+        escrow: &Pubkey,
+    ) -> Result<Option<IndexedSubscription>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT escrow, subscription_id, buyer, seller, payment_count, total_amount,
+                    is_active, closed
+             FROM subscriptions WHERE escrow = ?1",
+        )?;
+        let row = statement
+            .query_row(params![escrow.to_string()], subscription_from_row)
+            .optional()?;
+        row.map(|row| row.parse()).transpose()
+    }
+
+    /// Active subscriptions, optionally only those paying `seller`.
+    pub fn open_subscriptions(
+        &self,
+        seller: Option<&Pubkey>,
+    ) -> Result<Vec<IndexedSubscription>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT escrow, subscription_id, buyer, seller, payment_count, total_amount,
+                    is_active, closed
+             FROM subscriptions
+             WHERE is_active = 1 AND (?1 IS NULL OR seller = ?1)
+             ORDER BY started_slot",
+        )?;
+        let rows = statement.query_map(
+            params![seller.map(Pubkey::to_string)],
+            subscription_from_row,
+        )?;
+        rows.map(|row| row?.parse()).collect()
+    }
+
+    /// Lamports currently held in escrow by subscriptions that have not been withdrawn.
+    pub fn total_escrowed(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let total: i64 = self.connection.query_row(
+            "SELECT COALESCE(SUM(total_amount), 0) FROM subscriptions WHERE closed = 0",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(total as u64)
+    }
+
+    pub fn refunds(&self) -> Result<Vec<Refund>, Box<dyn std::error::Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT w.signature, w.escrow, s.buyer, w.slot, w.buyer_amount
+             FROM withdrawals w JOIN subscriptions s ON s.escrow = w.escrow
+             WHERE w.outcome = 'refunded_to_buyer'
+             ORDER BY w.slot",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (signature, escrow, buyer, slot, amount) = row?;
+            Ok(Refund {
+                signature: signature.parse()?,
+                escrow: escrow.parse()?,
+                buyer: buyer.parse()?,
+                slot: slot as u64,
+                amount: amount as u64,
+            })
+        })
+        .collect()
+    }
+}
+
+/// Subscription columns as stored, before the pubkeys are parsed.
+struct SubscriptionRow {
+    escrow: String,
+    subscription_id: String,
+    buyer: String,
+    seller: String,
+    payment_count: i64,
+    total_amount: i64,
+    is_active: bool,
+    closed: bool,
+}
+
+impl SubscriptionRow {
+    fn parse(self) -> Result<IndexedSubscription, Box<dyn std::error::Error>> {
+        Ok(IndexedSubscription {
+            escrow: self.escrow.parse()?,
+            subscription_id: self.subscription_id,
+            buyer: self.buyer.parse()?,
+            seller: self.seller.parse()?,
+            payment_count: self.payment_count as u64,
+            total_amount: self.total_amount as u64,
+            is_active: self.is_active,
+            closed: self.closed,
+        })
+    }
+}
+
+fn subscription_from_row(row: &rusqlite::Row) -> rusqlite::Result<SubscriptionRow> {
+    Ok(SubscriptionRow {
+        escrow: row.get(0)?,
+        subscription_id: row.get(1)?,
+        buyer: row.get(2)?,
+        seller: row.get(3)?,
+        payment_count: row.get(4)?,
+        total_amount: row.get(5)?,
+        is_active: row.get(6)?,
+        closed: row.get(7)?,
+    })
+}
+
+pub fn print_subscriptions(subscriptions: &[IndexedSubscription]) {
+    println!(
+        "{:<44}  {:<20}  {:<44}  {:>8}  {:>14}",
+        "Escrow", "Subscription ID", "Buyer", "Payments", "Escrowed (SOL)"
+    );
+    for subscription in subscriptions {
+        println!(
+            "{:<44}  {:<20}  {:<44}  {:>8}  {:>14}",
+            subscription.escrow.to_string(),
+            subscription.subscription_id,
+            subscription.buyer.to_string(),
+            subscription.payment_count,
+            format_sol_balance(subscription.total_amount)
+        );
+    }
+    println!("\n{} open subscriptions", subscriptions.len());
+}
+
+pub fn print_refunds(refunds: &[Refund]) {
+    println!(
+        "{:<44}  {:<44}  {:>10}  {:>14}",
+        "Escrow", "Buyer", "Slot", "Refund (SOL)"
+    );
+    for refund in refunds {
+        println!(
+            "{:<44}  {:<44}  {:>10}  {:>14}",
+            refund.escrow.to_string(),
+            refund.buyer.to_string(),
+            refund.slot,
+            format_sol_balance(refund.amount)
+        );
+    }
+    let total: u64 = refunds.iter().map(|refund| refund.amount).sum();
+    println!(
+        "\n{} refunds, {} SOL in total",
+        refunds.len(),
+        format_sol_balance(total)
+    );
+}
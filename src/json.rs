@@ -0,0 +1,148 @@
+//! Serde field helpers for the JSON form of escrow types, used with
+//! `#[serde(with = "...")]`.
+
+/// A pubkey as its base58 string.
+pub mod pubkey {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(pubkey)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A lamport amount as `{"lamports": 1500000000, "sol": 1.5}`. `sol` is for
+/// readers only and may be left out when writing JSON by hand, but must agree
+/// with `lamports` when present.
+pub mod lamports {
+    use crate::escrow::LAMPORTS_PER_SOL;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Amount {
+        lamports: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sol: Option<f64>,
+    }
+
+    pub fn serialize<S: Serializer>(lamports: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        Amount {
+            lamports: *lamports,
+            sol: Some(*lamports as f64 / LAMPORTS_PER_SOL as f64),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let amount = Amount::deserialize(deserializer)?;
+        // Compare in SOL: above 2^53 lamports an f64 can't hold every amount,
+        // and parsing may be a unit in the last place off what was written
+        let expected = amount.lamports as f64 / LAMPORTS_PER_SOL as f64;
+        match amount.sol {
+            Some(sol) if (sol - expected).abs() > expected * f64::EPSILON => {
+                Err(de::Error::custom(format!(
+                    "{} SOL does not match {} lamports",
+                    sol, amount.lamports
+                )))
+            }
+            _ => Ok(amount.lamports),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        escrow::{
+            self, tests::parties, EscrowAccount, MakePaymentArgs, StartSubscriptionArgs,
+            WithdrawFundsArgs,
+        },
+        layout::{parse_hex, tests::sample_escrow_account},
+    };
+    use anchor_lang::AnchorSerialize;
+
+    /// Parses a JSON fixture, checking that serializing it again reproduces
+    /// the file exactly.
+    fn json_fixture<T: serde::Serialize + serde::de::DeserializeOwned>(text: &str) -> T {
+        let value: T = serde_json::from_str(text).unwrap();
+        assert_eq!(serde_json::to_string_pretty(&value).unwrap() + "\n", text);
+        value
+    }
+
+    #[test]
+    fn instruction_args_match_golden_files() {
+        let (program_id, escrow, buyer, seller) = parties();
+
+        let args: StartSubscriptionArgs = json_fixture(include_str!(
+            "../fixtures/instruction_data/start_subscription.json"
+        ));
+        let instruction =
+            escrow::start_subscription(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(
+            instruction.data,
+            parse_hex(include_str!(
+                "../fixtures/instruction_data/start_subscription.hex"
+            ))
+            .unwrap()
+        );
+
+        let args: MakePaymentArgs = json_fixture(include_str!(
+            "../fixtures/instruction_data/make_payment.json"
+        ));
+        let instruction =
+            escrow::make_payment(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(
+            instruction.data,
+            parse_hex(include_str!(
+                "../fixtures/instruction_data/make_payment.hex"
+            ))
+            .unwrap()
+        );
+
+        let args: WithdrawFundsArgs = json_fixture(include_str!(
+            "../fixtures/instruction_data/withdraw_funds.json"
+        ));
+        let instruction =
+            escrow::withdraw_funds(&program_id, &escrow, &buyer, &seller, &args).unwrap();
+        assert_eq!(
+            instruction.data,
+            parse_hex(include_str!(
+                "../fixtures/instruction_data/withdraw_funds.hex"
+            ))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn escrow_account_matches_golden_values() {
+        let account: EscrowAccount =
+            json_fixture(include_str!("../fixtures/escrow_account/v1.json"));
+        assert_eq!(
+            account.try_to_vec().unwrap(),
+            sample_escrow_account().try_to_vec().unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trips_amounts_beyond_f64_precision() {
+        for amount in [u64::MAX, (1 << 53) + 1, 1_500_000_000, 1, 0] {
+            let json = serde_json::to_string(&MakePaymentArgs { amount }).unwrap();
+            let parsed: MakePaymentArgs = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed.amount, amount, "{} does not round-trip", json);
+        }
+    }
+
+    #[test]
+    fn rejects_sol_amount_disagreeing_with_lamports() {
+        assert!(serde_json::from_str::<MakePaymentArgs>(
+            r#"{"amount": {"lamports": 1, "sol": 1.0}}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use solana_client::{nonce_utils, rpc_client::RpcClient};
+use solana_sdk::{
+    hash::Hash,
+    nonce::State,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+
+/// Creates a rent-exempt durable nonce account at `nonce_account`,
+/// controlled by `authority`.
+pub fn create_nonce_account(
+    client: &RpcClient,
+    payer: &Keypair,
+    nonce_account: &Keypair,
+    authority: &Pubkey,
+) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let lamports = client.get_minimum_balance_for_rent_exemption(State::size())?;
+    let instructions = system_instruction::create_nonce_account(
+        &payer.pubkey(),
+        &nonce_account.pubkey(),
+        authority,
+        lamports,
+    );
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, nonce_account],
+        client.get_latest_blockhash()?,
+    );
+    client.send_and_confirm_transaction(&transaction)?;
+    Ok(nonce_account.pubkey())
+}
+
+/// Reads the durable blockhash currently stored in a nonce account.
+pub fn fetch_nonce(
+    client: &RpcClient,
+    nonce_account: &Pubkey,
+) -> Result<Hash, Box<dyn std::error::Error>> {
+    let account =
+        nonce_utils::get_account_with_commitment(client, nonce_account, client.commitment())?;
+    Ok(nonce_utils::data_from_account(&account)?.blockhash())
+}
@@ -0,0 +1,121 @@
+use crate::escrow::EscrowAccount;
+use anchor_client::{Client, Cluster};
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use clap::ValueEnum;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
+
+// `accounts` and `instruction` modules, generated from the program's IDL by build.rs.
+include!(concat!(env!("OUT_DIR"), "/program_generated.rs"));
+
+/// Client implementation escrow instructions are sent and escrow accounts read with.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientPath {
+    /// Instruction builders in `escrow`, sent with the transaction and retry options
+    #[default]
+    Raw,
+    /// anchor_client request builders and account fetching
+    Anchor,
+}
+
+/// anchor_client access to the escrow program on one cluster.
+///
+/// anchor_client fixes the fee payer per client, so one is created for each
+/// request. Requests are legacy transactions without compute budget
+/// instructions, sent and confirmed by anchor_client itself.
+pub struct AnchorProgram {
+    cluster: Cluster,
+    commitment: CommitmentConfig,
+    program_id: Pubkey,
+}
+
+impl AnchorProgram {
+    pub fn new(
+        rpc_url: &str,
+        ws_url: &str,
+        commitment: CommitmentConfig,
+        program_id: Pubkey,
+    ) -> Self {
+        Self {
+            cluster: Cluster::Custom(rpc_url.to_string(), ws_url.to_string()),
+            commitment,
+            program_id,
+        }
+    }
+
+    /// The instruction anchor_client would send for `accounts` and `args`.
+    /// Needs no cluster access.
+    pub fn instruction(
+        &self,
+        accounts: impl ToAccountMetas,
+        args: impl InstructionData,
+    ) -> Result<Instruction, Box<dyn std::error::Error>> {
+        // Never signs anything; building a request only needs some payer
+        let payer = Keypair::new();
+        let client = Client::new_with_options(self.cluster.clone(), &payer, self.commitment);
+        let program = client.program(self.program_id)?;
+        let mut instructions = program
+            .request()
+            .accounts(accounts)
+            .args(args)
+            .instructions()?;
+        instructions
+            .pop()
+            .ok_or_else(|| "anchor_client built no instruction".into())
+    }
+
+    /// Sends a single instruction paid for and signed by `payer`, and waits
+    /// for it to be confirmed.
+    pub async fn send(
+        &self,
+        payer: &Keypair,
+        accounts: impl ToAccountMetas,
+        args: impl InstructionData,
+    ) -> Result<Signature, Box<dyn std::error::Error>> {
+        let client = Client::new_with_options(self.cluster.clone(), payer, self.commitment);
+        let program = client.program(self.program_id)?;
+        Ok(program
+            .request()
+            .accounts(accounts)
+            .args(args)
+            .send()
+            .await?)
+    }
+
+    /// Fetches and decodes an escrow account, failing once it has been closed.
+    pub async fn escrow_account(
+        &self,
+        address: &Pubkey,
+    ) -> Result<EscrowAccount, Box<dyn std::error::Error>> {
+        let payer = Keypair::new();
+        let client = Client::new_with_options(self.cluster.clone(), &payer, self.commitment);
+        let program = client.program(self.program_id)?;
+        Ok(program.account::<EscrowAccount>(*address).await?)
+    }
+
+    /// Fetches an escrow account along with its lamports in one request,
+    /// decoded as `escrow_account` decodes it, or `None` once it has been closed.
+    pub async fn escrow_state(
+        &self,
+        address: &Pubkey,
+    ) -> Result<Option<(u64, EscrowAccount)>, Box<dyn std::error::Error>> {
+        let payer = Keypair::new();
+        let client = Client::new_with_options(self.cluster.clone(), &payer, self.commitment);
+        let program = client.program(self.program_id)?;
+        let account = program
+            .async_rpc()
+            .get_account_with_commitment(address, self.commitment)
+            .await?
+            .value;
+        account
+            .map(|account| {
+                let escrow_account = EscrowAccount::try_deserialize(&mut account.data.as_slice())?;
+                Ok((account.lamports, escrow_account))
+            })
+            .transpose()
+    }
+}
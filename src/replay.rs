@@ -0,0 +1,394 @@
+//! Recording of the RPC traffic of a run, and an `RpcClient` that serves a
+//! recording back in place of a cluster.
+//!
+//! A recording is a JSON lines file. The first line holds the seed the run's
+//! keypairs were derived from, so a replay signs the same transactions, and
+//! every further line holds one request and its response, in the order they
+//! were made. Replay hands out responses in that order as long as the methods
+//! line up. Requests made with different parameters are reported once but
+//! still answered from the recording, unless the replay is strict.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::{RpcClient, RpcClientConfig},
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_response::RpcSimulateTransactionResult,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// First line of a recording.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    seed: u64,
+}
+
+/// One request and what the cluster answered.
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    params: serde_json::Value,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Result(serde_json::Value),
+    Error(RecordedError),
+}
+
+/// The parts of a `ClientError` needed to rebuild it on replay.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum RecordedError {
+    /// JSON-RPC error response from the node
+    Response {
+        code: i64,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preflight_failure: Option<RpcSimulateTransactionResult>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unhealthy: Option<Option<u64>>,
+    },
+    /// Anything else, such as a connection failure, kept as its message
+    Other { message: String },
+}
+
+impl RecordedError {
+    fn record(error: &ClientError) -> Self {
+        match &error.kind {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code,
+                message,
+                data,
+            }) => {
+                let (preflight_failure, unhealthy) = match data {
+                    RpcResponseErrorData::Empty => (None, None),
+                    RpcResponseErrorData::SendTransactionPreflightFailure(result) => {
+                        (Some(result.clone()), None)
+                    }
+                    RpcResponseErrorData::NodeUnhealthy { num_slots_behind } => {
+                        (None, Some(*num_slots_behind))
+                    }
+                };
+                RecordedError::Response {
+                    code: *code,
+                    message: message.clone(),
+                    preflight_failure,
+                    unhealthy,
+                }
+            }
+            kind => RecordedError::Other {
+                message: kind.to_string(),
+            },
+        }
+    }
+
+    fn replay(self) -> ClientError {
+        match self {
+            RecordedError::Response {
+                code,
+                message,
+                preflight_failure,
+                unhealthy,
+            } => {
+                let data = match (preflight_failure, unhealthy) {
+                    (Some(result), _) => {
+                        RpcResponseErrorData::SendTransactionPreflightFailure(result)
+                    }
+                    (None, Some(num_slots_behind)) => {
+                        RpcResponseErrorData::NodeUnhealthy { num_slots_behind }
+                    }
+                    (None, None) => RpcResponseErrorData::Empty,
+                };
+                RpcError::RpcResponseError {
+                    code,
+                    message,
+                    data,
+                }
+                .into()
+            }
+            RecordedError::Other { message } => ClientErrorKind::Custom(message).into(),
+        }
+    }
+}
+
+/// Sends requests to the cluster and appends each exchange to a file.
+struct RecordingSender {
+    inner: HttpSender,
+    file: Mutex<File>,
+}
+
+#[async_trait]
+impl RpcSender for RecordingSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let result = self.inner.send(request, params.clone()).await;
+        let exchange = Exchange {
+            method: request.to_string(),
+            params,
+            outcome: match &result {
+                Ok(value) => Outcome::Result(value.clone()),
+                Err(e) => Outcome::Error(RecordedError::record(e)),
+            },
+        };
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&exchange)?)?;
+        file.flush()?;
+        result
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// Answers requests from a recording instead of a cluster.
+struct ReplaySender {
+    source: String,
+    exchanges: Mutex<VecDeque<Exchange>>,
+    served: AtomicUsize,
+    diverged: AtomicBool,
+    /// Fail on the first request whose parameters differ from the recording
+    strict: bool,
+}
+
+#[async_trait]
+impl RpcSender for ReplaySender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let number = self.served.fetch_add(1, Ordering::Relaxed) + 1;
+        let method = request.to_string();
+        let exchange = self.exchanges.lock().unwrap().pop_front().ok_or_else(|| {
+            ClientErrorKind::Custom(format!(
+                "Recording {} ended before request {} ({})",
+                self.source, number, method
+            ))
+        })?;
+        if exchange.method != method {
+            return Err(ClientErrorKind::Custom(format!(
+                "Replay diverged at request {}: recorded {}, made {}",
+                number, exchange.method, method
+            ))
+            .into());
+        }
+        if exchange.params != params && self.strict {
+            return Err(ClientErrorKind::Custom(format!(
+                "Replay diverged at request {} ({}): recorded parameters {}, made {}",
+                number, method, exchange.params, params
+            ))
+            .into());
+        }
+        if exchange.params != params && !self.diverged.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Replay: request {} ({}) differs from the recording, continuing with recorded responses",
+                number, method
+            );
+        }
+        match exchange.outcome {
+            Outcome::Result(value) => Ok(value),
+            Outcome::Error(error) => Err(error.replay()),
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats {
+            request_count: self.served.load(Ordering::Relaxed),
+            ..RpcTransportStats::default()
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("replay:{}", self.source)
+    }
+}
+
+/// A client for `url` that records all of its traffic to `path`, for a run
+/// whose keypairs are derived from `seed`.
+pub fn recording_client(
+    url: &str,
+    path: &Path,
+    commitment: CommitmentConfig,
+    seed: u64,
+) -> std::io::Result<RpcClient> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", serde_json::to_string(&Header { seed })?)?;
+    let sender = RecordingSender {
+        inner: HttpSender::new(url),
+        file: Mutex::new(file),
+    };
+    Ok(RpcClient::new_sender(
+        sender,
+        RpcClientConfig::with_commitment(commitment),
+    ))
+}
+
+/// A recording loaded for replay.
+pub struct Recording {
+    /// Seed the recorded run derived its keypairs from
+    pub seed: u64,
+    source: String,
+    exchanges: VecDeque<Exchange>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&fs::read_to_string(path)?, &path.display().to_string())
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(text: &str, source: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let header: Header = match lines.next() {
+            Some((_, line)) => serde_json::from_str(line)
+                .map_err(|e| format!("line 1 is not a recording header: {}", e))?,
+            None => return Err("recording is empty".to_string()),
+        };
+        let exchanges = lines
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|e| format!("line {}: {}", number + 1, e))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            seed: header.seed,
+            source: source.to_string(),
+            exchanges,
+        })
+    }
+
+    /// Number of recorded requests.
+    pub fn len(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// A client answering from the recording. A strict one fails on requests
+    /// whose parameters differ from the recorded ones instead of warning.
+    pub fn into_client(self, commitment: CommitmentConfig, strict: bool) -> RpcClient {
+        let sender = ReplaySender {
+            source: self.source,
+            exchanges: Mutex::new(self.exchanges),
+            served: AtomicUsize::new(0),
+            diverged: AtomicBool::new(false),
+            strict,
+        };
+        RpcClient::new_sender(sender, RpcClientConfig::with_commitment(commitment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+
+    /// A recording of two `getBalance` requests for `owner`, the first
+    /// answered and the second failed.
+    fn recording(owner: &Pubkey) -> String {
+        // The client asks for the node version before its first request
+        format!(
+            "{}\n{}\n{}\n{}\n",
+            serde_json::json!({ "seed": 7 }),
+            serde_json::json!({
+                "method": "getVersion",
+                "params": null,
+                "result": { "solana-core": "1.18.26", "feature-set": 0 },
+            }),
+            serde_json::json!({
+                "method": "getBalance",
+                "params": [owner.to_string(), { "commitment": "confirmed" }],
+                "result": { "context": { "slot": 7 }, "value": 42 },
+            }),
+            serde_json::json!({
+                "method": "getBalance",
+                "params": [owner.to_string(), { "commitment": "confirmed" }],
+                "error": { "kind": "response", "code": -32005, "message": "Node is unhealthy", "unhealthy": 3 },
+            }),
+        )
+    }
+
+    #[test]
+    fn replays_responses_and_errors_in_order() {
+        let owner = Pubkey::new_from_array([1; 32]);
+        let loaded = Recording::parse(&recording(&owner), "test").unwrap();
+        assert_eq!(loaded.seed, 7, "Recording should carry its run's seed");
+        assert_eq!(loaded.len(), 3);
+        let client = loaded.into_client(CommitmentConfig::confirmed(), false);
+
+        assert_eq!(client.get_balance(&owner).unwrap(), 42);
+        let unhealthy = client
+            .get_balance(&owner)
+            .expect_err("Recorded error should be replayed");
+        assert!(
+            matches!(
+                unhealthy.kind(),
+                ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                    code: -32005,
+                    data: RpcResponseErrorData::NodeUnhealthy {
+                        num_slots_behind: Some(3)
+                    },
+                    ..
+                })
+            ),
+            "Unexpected replayed error: {:?}",
+            unhealthy
+        );
+        let exhausted = client
+            .get_balance(&owner)
+            .expect_err("Exhausted recording should fail");
+        assert!(
+            exhausted.to_string().contains("ended before request 4"),
+            "Unexpected error: {}",
+            exhausted
+        );
+    }
+
+    #[test]
+    fn strict_replay_rejects_diverging_requests() {
+        let owner = Pubkey::new_from_array([1; 32]);
+        let strict = Recording::parse(&recording(&owner), "test")
+            .unwrap()
+            .into_client(CommitmentConfig::confirmed(), true);
+        let diverged = strict
+            .get_balance(&Pubkey::new_from_array([2; 32]))
+            .expect_err("Request for another account should diverge");
+        assert!(
+            diverged.to_string().contains("recorded parameters"),
+            "Unexpected error: {}",
+            diverged
+        );
+    }
+
+    #[test]
+    fn requires_a_seed_header() {
+        let owner = Pubkey::new_from_array([1; 32]);
+        let recording = recording(&owner).replacen("{\"seed\":7}", "", 1);
+        assert!(Recording::parse(&recording, "test").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    balances::{format_sol_balance, BalanceSnapshot},
+    escrow::EscrowAccount,
+    simulation::SimulationRecord,
+};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, fs::File, io::Write, path::Path};
+
+/// Everything captured during a harness run, written out with `test --report`.
+#[derive(Default)]
+pub struct Report {
+    pub simulations: Vec<SimulationRecord>,
+    /// Highest compute unit usage seen for each instruction
+    pub compute_units: BTreeMap<String, u64>,
+    pub balances: Vec<BalanceSnapshot>,
+}
+
+impl Report {
+    pub fn record_compute_units(&mut self, instruction: &str, units: u64) {
+        let entry = self
+            .compute_units
+            .entry(instruction.to_string())
+            .or_default();
+        *entry = (*entry).max(units);
+    }
+
+    pub fn record_balances(&mut self, snapshot: &BalanceSnapshot) {
+        self.balances.push(snapshot.clone());
+    }
+
+    /// Writes the report as JSON if `path` ends in `.json`, as text otherwise.
+    pub fn write(
+        &self,
+        path: &Path,
+        program_id: &Pubkey,
+        outcome: &Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = File::create(path)?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            serde_json::to_writer_pretty(&mut file, &self.json(program_id, outcome))?;
+            writeln!(file)?;
+            return Ok(());
+        }
+
+        writeln!(file, "Escrow test report")?;
+        writeln!(file, "Program: {}", program_id)?;
+        match outcome {
+            Ok(()) => writeln!(file, "Outcome: passed")?,
+            Err(e) => writeln!(file, "Outcome: failed ({})", e)?,
+        }
+        writeln!(file, "Compute units:")?;
+        for (instruction, units) in &self.compute_units {
+            writeln!(file, "  {}: {}", instruction, units)?;
+        }
+        writeln!(file, "Balances:")?;
+        for snapshot in &self.balances {
+            let accounts: Vec<String> = snapshot
+                .accounts
+                .iter()
+                .map(|balance| {
+                    format!(
+                        "{} {} SOL",
+                        balance.label,
+                        format_sol_balance(balance.lamports)
+                    )
+                })
+                .collect();
+            writeln!(file, "  {}: {}", snapshot.label, accounts.join(", "))?;
+        }
+        writeln!(file, "Simulated transactions: {}", self.simulations.len())?;
+        for record in &self.simulations {
+            record.write(&mut file, program_id)?;
+        }
+        Ok(())
+    }
+
+    fn json<'a>(
+        &'a self,
+        program_id: &Pubkey,
+        outcome: &Result<(), Box<dyn std::error::Error>>,
+    ) -> JsonReport<'a> {
+        JsonReport {
+            program: *program_id,
+            outcome: if outcome.is_ok() { "passed" } else { "failed" },
+            error: outcome.as_ref().err().map(|e| e.to_string()),
+            compute_units: &self.compute_units,
+            balances: &self.balances,
+            simulations: self
+                .simulations
+                .iter()
+                .map(|record| JsonSimulation {
+                    label: &record.label,
+                    error: record.err.as_deref(),
+                    units_consumed: record.units_consumed,
+                    logs: &record.logs,
+                    post_states: record
+                        .accounts
+                        .iter()
+                        .map(|post_state| JsonPostState {
+                            address: post_state.address,
+                            closed: post_state.account.is_none(),
+                            lamports: post_state
+                                .account
+                                .as_ref()
+                                .map_or(0, |account| account.lamports),
+                            escrow: post_state.escrow_account(program_id),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    #[serde(with = "crate::json::pubkey")]
+    program: Pubkey,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    compute_units: &'a BTreeMap<String, u64>,
+    balances: &'a [BalanceSnapshot],
+    simulations: Vec<JsonSimulation<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSimulation<'a> {
+    label: &'a str,
+    error: Option<&'a str>,
+    units_consumed: Option<u64>,
+    logs: &'a [String],
+    post_states: Vec<JsonPostState>,
+}
+
+#[derive(Serialize)]
+struct JsonPostState {
+    #[serde(with = "crate::json::pubkey")]
+    address: Pubkey,
+    closed: bool,
+    #[serde(with = "crate::json::lamports")]
+    lamports: u64,
+    /// Decoded escrow account, if the post-state is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    escrow: Option<EscrowAccount>,
+}
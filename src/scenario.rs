@@ -0,0 +1,440 @@
+use crate::escrow::DEFAULT_VALIDATION_THRESHOLD;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// An escrow flow described in a TOML file and run by `test --scenario`.
+///
+/// ```toml
+/// name = "Successful withdrawal"
+///
+/// [actors.buyer]
+/// airdrop_sol = 10.0
+///
+/// [actors.seller]
+/// airdrop_sol = 1.0
+///
+/// [subscriptions.main]
+/// buyer = "buyer"
+/// seller = "seller"
+///
+/// [[steps]]
+/// action = "start"
+/// subscription = "main"
+///
+/// [[steps]]
+/// action = "pay"
+/// subscription = "main"
+/// amount_sol = 1.0
+/// times = 5
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Keypairs created for the scenario, by name
+    pub actors: BTreeMap<String, Actor>,
+    /// Escrows between two actors, by name
+    #[serde(default)]
+    pub subscriptions: BTreeMap<String, Subscription>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Actor {
+    /// Airdropped before the first step
+    #[serde(default)]
+    pub airdrop_sol: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    pub buyer: String,
+    pub seller: String,
+    /// Subscription ID the PDA is derived from, defaults to the subscription's name
+    pub id: Option<String>,
+    #[serde(default = "default_validation_threshold")]
+    pub validation_threshold: u64,
+}
+
+fn default_validation_threshold() -> u64 {
+    DEFAULT_VALIDATION_THRESHOLD
+}
+
+fn once() -> u32 {
+    1
+}
+
+/// One step of a scenario. Transaction steps succeed unless `expect_error`
+/// is set, in which case they must fail with an error containing it. An
+/// empty `expect_error` accepts any error.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Start the subscription, signed by its buyer
+    Start {
+        subscription: String,
+        expect_error: Option<String>,
+    },
+    /// Pay `amount_sol` into the subscription `times` times, signed by its buyer
+    Pay {
+        subscription: String,
+        amount_sol: f64,
+        #[serde(default = "once")]
+        times: u32,
+        expect_error: Option<String>,
+    },
+    /// Cancel the subscription, signed by its buyer
+    Cancel {
+        subscription: String,
+        expect_error: Option<String>,
+    },
+    /// Withdraw from the subscription, signed by its seller
+    Withdraw {
+        subscription: String,
+        validation_data: u64,
+        expect_error: Option<String>,
+    },
+    /// Record the balances of every actor and escrow under `label`
+    Snapshot { label: String },
+    /// Check the escrow account. Unset fields are not checked
+    ExpectState {
+        subscription: String,
+        closed: Option<bool>,
+        is_active: Option<bool>,
+        payment_count: Option<u8>,
+        total_amount_sol: Option<f64>,
+    },
+    /// Check balance changes of actors and escrows, in SOL, since a snapshot.
+    /// Transaction fees paid by an actor are added back, so deltas describe
+    /// the escrow flows alone
+    ExpectBalances {
+        since: String,
+        deltas: BTreeMap<String, f64>,
+        #[serde(default)]
+        tolerance_sol: f64,
+        /// Escrows closed since the snapshot, by the actor their rent went
+        /// to. The rent they held at the snapshot is added to the deltas of
+        /// both, so deltas only need the escrowed amounts
+        #[serde(default)]
+        rent_refunds: BTreeMap<String, String>,
+    },
+}
+
+impl Step {
+    /// The subscription a step acts on or checks, if any.
+    pub fn subscription(&self) -> Option<&str> {
+        match self {
+            Step::Start { subscription, .. }
+            | Step::Pay { subscription, .. }
+            | Step::Cancel { subscription, .. }
+            | Step::Withdraw { subscription, .. }
+            | Step::ExpectState { subscription, .. } => Some(subscription),
+            Step::Snapshot { .. } | Step::ExpectBalances { .. } => None,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Parses and validates a scenario.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let scenario: Scenario = toml::from_str(text).map_err(|e| e.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Checks that every name a scenario refers to is defined before a
+    /// validator is needed to find out.
+    fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (name, actor) in &self.actors {
+            if !(actor.airdrop_sol.is_finite() && actor.airdrop_sol >= 0.0) {
+                errors.push(format!("actor {} must airdrop a finite amount", name));
+            }
+        }
+        for (name, subscription) in &self.subscriptions {
+            for actor in [&subscription.buyer, &subscription.seller] {
+                if !self.actors.contains_key(actor) {
+                    errors.push(format!(
+                        "subscription {} refers to unknown actor {}",
+                        name, actor
+                    ));
+                }
+            }
+            if self.actors.contains_key(name) {
+                errors.push(format!("{} is both an actor and a subscription", name));
+            }
+        }
+
+        let mut snapshots = Vec::new();
+        for (number, step) in self.steps.iter().enumerate() {
+            let step_name = format!("step {}", number + 1);
+            if let Some(subscription) = step.subscription() {
+                if !self.subscriptions.contains_key(subscription) {
+                    errors.push(format!(
+                        "{} refers to unknown subscription {}",
+                        step_name, subscription
+                    ));
+                }
+            }
+            match step {
+                // NaN fails every comparison, so check for the valid range
+                Step::Pay {
+                    amount_sol, times, ..
+                } if !(amount_sol.is_finite() && *amount_sol > 0.0) || *times == 0 => {
+                    errors.push(format!(
+                        "{} must pay a finite positive amount at least once",
+                        step_name
+                    ))
+                }
+                Step::ExpectState {
+                    total_amount_sol: Some(total_amount_sol),
+                    ..
+                } if !(total_amount_sol.is_finite() && *total_amount_sol >= 0.0) => {
+                    errors.push(format!("{} must expect a finite total amount", step_name))
+                }
+                Step::Snapshot { label } => snapshots.push(label),
+                Step::ExpectBalances {
+                    since,
+                    deltas,
+                    tolerance_sol,
+                    rent_refunds,
+                } => {
+                    if !(tolerance_sol.is_finite() && *tolerance_sol >= 0.0) {
+                        errors.push(format!("{} must have a finite tolerance", step_name));
+                    }
+                    if deltas.values().any(|delta| !delta.is_finite()) {
+                        errors.push(format!("{} must expect finite deltas", step_name));
+                    }
+                    if !snapshots.contains(&since) {
+                        errors.push(format!(
+                            "{} compares to snapshot {}, which is not taken before it",
+                            step_name, since
+                        ));
+                    }
+                    for account in deltas.keys() {
+                        if !self.actors.contains_key(account)
+                            && !self.subscriptions.contains_key(account)
+                        {
+                            errors.push(format!(
+                                "{} expects a delta for unknown account {}",
+                                step_name, account
+                            ));
+                        }
+                    }
+                    for (subscription, actor) in rent_refunds {
+                        if !self.subscriptions.contains_key(subscription)
+                            || !self.actors.contains_key(actor)
+                        {
+                            errors.push(format!(
+                                "{} refunds rent from {} to {}, which must be a subscription and an actor",
+                                step_name, subscription, actor
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROKEN: &str = r#"
+        name = "Broken"
+
+        [actors.buyer]
+
+        [subscriptions.main]
+        buyer = "buyer"
+        seller = "nobody"
+
+        [[steps]]
+        action = "pay"
+        subscription = "other"
+        amount_sol = 1.0
+
+        [[steps]]
+        action = "expect_balances"
+        since = "later"
+        deltas = { buyer = -1.0 }
+        rent_refunds = { buyer = "main" }
+
+        [[steps]]
+        action = "snapshot"
+        label = "later"
+    "#;
+
+    /// A buyer and seller with one subscription between them, followed by
+    /// `steps`.
+    fn scenario(steps: &str) -> String {
+        format!(
+            r#"
+            name = "Payment"
+
+            [actors.buyer]
+            [actors.seller]
+
+            [subscriptions.main]
+            buyer = "buyer"
+            seller = "seller"
+
+            {}
+            "#,
+            steps
+        )
+    }
+
+    fn payment(amount_sol: &str, times: u32) -> String {
+        scenario(&format!(
+            r#"
+            [[steps]]
+            action = "pay"
+            subscription = "main"
+            amount_sol = {}
+            times = {}
+            "#,
+            amount_sol, times
+        ))
+    }
+
+    /// Errors reported for a scenario that must not parse.
+    fn errors(text: &str) -> String {
+        match Scenario::parse(text) {
+            Ok(_) => panic!("Scenario should not parse:\n{}", text),
+            Err(errors) => errors,
+        }
+    }
+
+    #[test]
+    fn parses_bundled_scenarios() {
+        for (name, text) in [
+            (
+                "successful_withdrawal",
+                include_str!("../scenarios/successful_withdrawal.toml"),
+            ),
+            (
+                "failed_withdrawal",
+                include_str!("../scenarios/failed_withdrawal.toml"),
+            ),
+            (
+                "unaffordable_payment",
+                include_str!("../scenarios/unaffordable_payment.toml"),
+            ),
+        ] {
+            if let Err(e) = Scenario::parse(text) {
+                panic!("{}: {}", name, e);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_every_error_at_once() {
+        let errors = errors(BROKEN);
+        for expected in [
+            "unknown actor nobody",
+            "unknown subscription other",
+            "snapshot later, which is not taken before it",
+            "refunds rent from buyer to main",
+        ] {
+            assert!(
+                errors.contains(expected),
+                "Expected \"{}\" among the errors:\n{}",
+                expected,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        // Otherwise valid, so only the misspelled field can fail it
+        assert!(Scenario::parse(&payment("1.0", 1).replace("amount_sol", "amount")).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_actors() {
+        let text = payment("1.0", 1).replace(r#"seller = "seller""#, r#"seller = "nobody""#);
+        let errors = errors(&text);
+        assert!(
+            errors.contains("subscription main refers to unknown actor nobody"),
+            "Unexpected errors:\n{}",
+            errors
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_subscriptions() {
+        let text =
+            payment("1.0", 1).replace(r#"subscription = "main""#, r#"subscription = "other""#);
+        let errors = errors(&text);
+        assert!(
+            errors.contains("step 1 refers to unknown subscription other"),
+            "Unexpected errors:\n{}",
+            errors
+        );
+    }
+
+    #[test]
+    fn rejects_snapshots_used_before_they_are_taken() {
+        let text = scenario(
+            r#"
+            [[steps]]
+            action = "expect_balances"
+            since = "later"
+            deltas = { buyer = -1.0 }
+
+            [[steps]]
+            action = "snapshot"
+            label = "later"
+            "#,
+        );
+        let errors = errors(&text);
+        assert!(
+            errors.contains("step 1 compares to snapshot later, which is not taken before it"),
+            "Unexpected errors:\n{}",
+            errors
+        );
+    }
+
+    #[test]
+    fn rejects_non_finite_and_negative_amounts() {
+        Scenario::parse(&payment("1.0", 1)).unwrap();
+        for amount_sol in ["nan", "inf", "-1.0", "0.0"] {
+            let errors = errors(&payment(amount_sol, 1));
+            assert!(
+                errors.contains("must pay a finite positive amount"),
+                "amount_sol = {} rejected for the wrong reason:\n{}",
+                amount_sol,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_paying_zero_times() {
+        let errors = errors(&payment("1.0", 0));
+        assert!(
+            errors.contains("must pay a finite positive amount at least once"),
+            "Unexpected errors:\n{}",
+            errors
+        );
+    }
+}
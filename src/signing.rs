@@ -0,0 +1,156 @@
+use crate::transaction;
+use solana_sdk::{
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+
+/// A transaction whose required signatures are collected one party at a time.
+///
+/// Missing signatures are held as the default (all-zero) signature, so a
+/// partially signed transaction travels in the same base64 encoding as a
+/// complete one.
+#[derive(Clone)]
+pub struct PartialTransaction {
+    transaction: VersionedTransaction,
+}
+
+impl PartialTransaction {
+    pub fn new(message: VersionedMessage) -> Self {
+        let signatures =
+            vec![Signature::default(); usize::from(message.header().num_required_signatures)];
+        Self {
+            transaction: VersionedTransaction {
+                signatures,
+                message,
+            },
+        }
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let transaction = transaction::decode_transaction(encoded)?;
+        // Rejects headers claiming more signers than there are account keys
+        transaction
+            .message
+            .sanitize()
+            .map_err(|e| format!("Malformed transaction message: {}", e))?;
+        if transaction.signatures.len()
+            != usize::from(transaction.message.header().num_required_signatures)
+        {
+            return Err("Transaction signature count does not match its message header".into());
+        }
+        Ok(Self { transaction })
+    }
+
+    pub fn encode(&self) -> Result<String, Box<dyn std::error::Error>> {
+        transaction::encode_transaction(&self.transaction)
+    }
+
+    /// Accounts that must sign, fee payer first.
+    pub fn signers(&self) -> &[Pubkey] {
+        let num_required_signatures =
+            usize::from(self.transaction.message.header().num_required_signatures);
+        &self.transaction.message.static_account_keys()[..num_required_signatures]
+    }
+
+    pub fn missing_signers(&self) -> Vec<Pubkey> {
+        self.signers()
+            .iter()
+            .zip(&self.transaction.signatures)
+            .filter(|(_, signature)| **signature == Signature::default())
+            .map(|(signer, _)| *signer)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing_signers().is_empty()
+    }
+
+    /// Signs the message with `keypair` and returns the signature, which can
+    /// also be handed to another party as base58 and applied with `add_signature`.
+    pub fn sign(&mut self, keypair: &Keypair) -> Result<Signature, Box<dyn std::error::Error>> {
+        let signature = keypair.sign_message(&self.transaction.message.serialize());
+        self.add_signature(&keypair.pubkey(), signature)?;
+        Ok(signature)
+    }
+
+    /// Adds a signature produced elsewhere, after checking it against the message.
+    pub fn add_signature(
+        &mut self,
+        signer: &Pubkey,
+        signature: Signature,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let index = self
+            .signers()
+            .iter()
+            .position(|required| required == signer)
+            .ok_or_else(|| format!("{} is not a required signer", signer))?;
+        if !signature.verify(signer.as_ref(), &self.transaction.message.serialize()) {
+            return Err(format!("Signature does not match {} for this message", signer).into());
+        }
+        self.transaction.signatures[index] = signature;
+        Ok(())
+    }
+
+    /// Returns the signed transaction, failing while any signature is missing.
+    pub fn into_transaction(self) -> Result<VersionedTransaction, Box<dyn std::error::Error>> {
+        let missing = self.missing_signers();
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.iter().map(Pubkey::to_string).collect();
+            return Err(format!("Missing signatures from {}", missing.join(", ")).into());
+        }
+        Ok(self.transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{
+        hash::Hash,
+        message::{Message, MessageHeader},
+    };
+
+    /// An encoded transaction over two keys with the given header and number
+    /// of signatures.
+    fn encode(num_required_signatures: u8, num_signatures: usize) -> String {
+        let message = VersionedMessage::Legacy(Message {
+            header: MessageHeader {
+                num_required_signatures,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 0,
+            },
+            account_keys: vec![
+                Pubkey::new_from_array([1; 32]),
+                Pubkey::new_from_array([2; 32]),
+            ],
+            recent_blockhash: Hash::default(),
+            instructions: Vec::new(),
+        });
+        transaction::encode_transaction(&VersionedTransaction {
+            signatures: vec![Signature::default(); num_signatures],
+            message,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_unsigned_transactions() {
+        let partial = PartialTransaction::decode(&encode(2, 2)).unwrap();
+        assert_eq!(partial.missing_signers().len(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_transactions() {
+        for (num_required_signatures, num_signatures) in [(3, 3), (2, 1)] {
+            assert!(
+                PartialTransaction::decode(&encode(num_required_signatures, num_signatures))
+                    .is_err(),
+                "{} signatures for {} required signers over 2 keys should not decode",
+                num_signatures,
+                num_required_signatures
+            );
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{balances::format_sol_balance, escrow::EscrowAccount, layout};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig},
+};
+use solana_sdk::{account::Account, pubkey::Pubkey, transaction::VersionedTransaction};
+use std::io::Write;
+
+/// Outcome of running a transaction through `simulateTransaction`.
+pub struct SimulationRecord {
+    pub label: String,
+    pub err: Option<String>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+    pub accounts: Vec<AccountPostState>,
+}
+
+/// State of a writable account after the simulated transaction, `None` if it was closed.
+pub struct AccountPostState {
+    pub address: Pubkey,
+    pub account: Option<Account>,
+}
+
+/// Simulates `transaction`, capturing logs, compute units and the post-state
+/// of every statically referenced account it writes to.
+pub fn simulate(
+    client: &RpcClient,
+    label: &str,
+    transaction: &VersionedTransaction,
+) -> Result<SimulationRecord, Box<dyn std::error::Error>> {
+    let writable: Vec<Pubkey> = transaction
+        .message
+        .static_account_keys()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| transaction.message.is_maybe_writable(*i))
+        .map(|(_, key)| *key)
+        .collect();
+
+    let config = RpcSimulateTransactionConfig {
+        commitment: Some(client.commitment()),
+        accounts: Some(RpcSimulateTransactionAccountsConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            addresses: writable.iter().map(Pubkey::to_string).collect(),
+        }),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = client
+        .simulate_transaction_with_config(transaction, config)?
+        .value;
+
+    let accounts = writable
+        .into_iter()
+        .zip(result.accounts.unwrap_or_default())
+        .map(|(address, account)| AccountPostState {
+            address,
+            account: account.and_then(|account| account.decode::<Account>()),
+        })
+        .collect();
+
+    Ok(SimulationRecord {
+        label: label.to_string(),
+        err: result.err.map(|err| err.to_string()),
+        logs: result.logs.unwrap_or_default(),
+        units_consumed: result.units_consumed,
+        accounts,
+    })
+}
+
+impl SimulationRecord {
+    pub fn print(&self, program_id: &Pubkey) {
+        // Writing to stdout only fails if stdout itself is gone
+        let _ = self.write(&mut std::io::stdout(), program_id);
+    }
+
+    pub fn write(&self, out: &mut dyn Write, program_id: &Pubkey) -> std::io::Result<()> {
+        writeln!(out, "\n=== Simulation of {} ===", self.label)?;
+        match &self.err {
+            Some(err) => writeln!(out, "Result: failed ({})", err)?,
+            None => writeln!(out, "Result: success")?,
+        }
+        if let Some(units) = self.units_consumed {
+            writeln!(out, "Compute units consumed: {}", units)?;
+        }
+        writeln!(out, "Logs:")?;
+        for log in &self.logs {
+            writeln!(out, "  {}", log)?;
+        }
+        writeln!(out, "Post-states:")?;
+        for post_state in &self.accounts {
+            match &post_state.account {
+                Some(account) => writeln!(
+                    out,
+                    "  {}: {} SOL, {} bytes, owner {}",
+                    post_state.address,
+                    format_sol_balance(account.lamports),
+                    account.data.len(),
+                    account.owner
+                )?,
+                None => writeln!(out, "  {}: closed", post_state.address)?,
+            }
+            if let Some(escrow_account) = post_state.escrow_account(program_id) {
+                writeln!(
+                    out,
+                    "    escrow: subscription_id={}, payment_count={}, total_amount={}, is_active={}",
+                    escrow_account.subscription_id,
+                    escrow_account.payment_count,
+                    escrow_account.total_amount,
+                    escrow_account.is_active
+                )?;
+            }
+        }
+        writeln!(out, "========================")
+    }
+}
+
+impl AccountPostState {
+    /// Decodes the post-state as an escrow account when it is owned by the program.
+    pub fn escrow_account(&self, program_id: &Pubkey) -> Option<EscrowAccount> {
+        let account = self.account.as_ref()?;
+        if account.owner != *program_id {
+            return None;
+        }
+        layout::decode_escrow_account(&account.data)
+            .ok()
+            .map(|decoded| decoded.account)
+    }
+}
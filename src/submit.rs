@@ -0,0 +1,258 @@
+use clap::{Args, ValueEnum};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    hash::Hash,
+    signature::Signature,
+    transaction::{TransactionError, VersionedTransaction},
+};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long past the attempt timeout a transaction that would be re-signed is
+/// waited on for its blockhash to expire, which takes about a minute.
+const EXPIRY_WAIT: Duration = Duration::from_secs(120);
+
+/// Commitment level transactions must reach before they count as confirmed.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Commitment {
+    Processed,
+    #[default]
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub fn config(self) -> CommitmentConfig {
+        let commitment = match self {
+            Commitment::Processed => CommitmentLevel::Processed,
+            Commitment::Confirmed => CommitmentLevel::Confirmed,
+            Commitment::Finalized => CommitmentLevel::Finalized,
+        };
+        CommitmentConfig { commitment }
+    }
+}
+
+/// Commitment for reading transaction history: `commitment`, raised to
+/// confirmed when lower, since getTransaction and getSignaturesForAddress
+/// reject processed.
+pub fn history_commitment(commitment: CommitmentConfig) -> CommitmentConfig {
+    if commitment.is_at_least_confirmed() {
+        commitment
+    } else {
+        CommitmentConfig::confirmed()
+    }
+}
+
+/// Command-line options bounding how long a transaction is retried.
+#[derive(Args, Clone)]
+pub struct RetryOptions {
+    /// Number of times a transaction is signed and broadcast before giving up
+    #[arg(long, default_value_t = 3)]
+    max_attempts: u32,
+
+    /// Seconds to wait for confirmation in each attempt. A transaction that
+    /// would be re-signed is waited on until its blockhash expires
+    #[arg(long, default_value_t = 60)]
+    attempt_timeout: u64,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            attempt_timeout: 60,
+        }
+    }
+}
+
+impl RetryOptions {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            attempt_timeout: Duration::from_secs(self.attempt_timeout),
+        }
+    }
+}
+
+/// Bounds on resubmitting a transaction. Confirmation is checked at the
+/// commitment level of the client the transaction is sent through.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub attempt_timeout: Duration,
+}
+
+enum AttemptOutcome {
+    Landed,
+    Expired,
+}
+
+/// Sends an already signed transaction, rebroadcasting it until it is
+/// confirmed or every attempt has timed out.
+///
+/// The transaction cannot be re-signed, so this suits durable nonce and
+/// multi-party transactions. A signature that already landed is returned
+/// without sending the transaction again.
+pub fn send_with_retry(
+    client: &RpcClient,
+    transaction: &VersionedTransaction,
+    policy: &RetryPolicy,
+) -> Result<Signature, Box<dyn std::error::Error>> {
+    let signature = transaction.signatures[0];
+    for attempt in 1..=policy.max_attempts {
+        if landed(client, &signature)? {
+            return Ok(signature);
+        }
+        match attempt_confirmation(client, transaction, policy, None)? {
+            AttemptOutcome::Landed => return Ok(signature),
+            AttemptOutcome::Expired => {
+                println!(
+                    "Transaction {} not confirmed (attempt {} of {})",
+                    signature, attempt, policy.max_attempts
+                );
+            }
+        }
+    }
+    Err(format!(
+        "Transaction {} not confirmed after {} attempts",
+        signature, policy.max_attempts
+    )
+    .into())
+}
+
+/// Signs and sends a transaction with `sign`, re-signing it against a fresh
+/// blockhash whenever the previous one expires before confirmation.
+///
+/// A transaction is only re-signed once its blockhash is past its last valid
+/// block height, and its signature is looked up once more before that so a
+/// transaction that landed late is never executed twice.
+pub fn send_with_resign(
+    client: &RpcClient,
+    policy: &RetryPolicy,
+    mut sign: impl FnMut(Hash) -> Result<VersionedTransaction, Box<dyn std::error::Error>>,
+) -> Result<Signature, Box<dyn std::error::Error>> {
+    let mut previous: Option<Signature> = None;
+    for attempt in 1..=policy.max_attempts {
+        if let Some(signature) = previous {
+            if landed(client, &signature)? {
+                return Ok(signature);
+            }
+        }
+
+        let (blockhash, last_valid_block_height) =
+            client.get_latest_blockhash_with_commitment(client.commitment())?;
+        let transaction = sign(blockhash)?;
+        let signature = transaction.signatures[0];
+
+        match attempt_confirmation(client, &transaction, policy, Some(last_valid_block_height))? {
+            AttemptOutcome::Landed => return Ok(signature),
+            AttemptOutcome::Expired => {
+                println!(
+                    "Blockhash expired before {} was confirmed (attempt {} of {}), re-signing",
+                    signature, attempt, policy.max_attempts
+                );
+            }
+        }
+        previous = Some(signature);
+    }
+    if let Some(signature) = previous {
+        if landed(client, &signature)? {
+            return Ok(signature);
+        }
+    }
+    Err(format!(
+        "Transaction not confirmed after {} attempts",
+        policy.max_attempts
+    )
+    .into())
+}
+
+/// Looks the signature up in the full transaction history, failing if it
+/// landed with an error.
+fn landed(client: &RpcClient, signature: &Signature) -> Result<bool, Box<dyn std::error::Error>> {
+    match client.get_signature_status_with_commitment_and_history(
+        signature,
+        client.commitment(),
+        true,
+    )? {
+        Some(Ok(())) => Ok(true),
+        Some(Err(err)) => Err(format!("Transaction {} failed: {}", signature, err).into()),
+        None => Ok(false),
+    }
+}
+
+/// Broadcasts `transaction` and polls its status until it reaches the client's
+/// commitment, its blockhash expires or the attempt times out.
+///
+/// With `last_valid_block_height`, the caller re-signs expired transactions,
+/// so the attempt only ends once the blockhash has expired. A transaction
+/// whose blockhash outlives the timeout by `EXPIRY_WAIT` has an unknown
+/// outcome and fails the send.
+fn attempt_confirmation(
+    client: &RpcClient,
+    transaction: &VersionedTransaction,
+    policy: &RetryPolicy,
+    last_valid_block_height: Option<u64>,
+) -> Result<AttemptOutcome, Box<dyn std::error::Error>> {
+    let signature = transaction.signatures[0];
+    if let Err(err) = client.send_transaction_with_config(transaction, send_config(client, false)) {
+        match err.get_transaction_error() {
+            Some(TransactionError::BlockhashNotFound) => return Ok(AttemptOutcome::Expired),
+            // Sent by an earlier attempt; its status decides the outcome
+            Some(TransactionError::AlreadyProcessed) => {}
+            _ => return Err(err.into()),
+        }
+    }
+
+    let deadline = Instant::now() + policy.attempt_timeout;
+    loop {
+        // The block height is read before the status, so once the blockhash
+        // has expired the status below is final
+        let expired = match last_valid_block_height {
+            Some(last_valid_block_height) => client.get_block_height()? > last_valid_block_height,
+            None => false,
+        };
+        match client.get_signature_status_with_commitment(&signature, client.commitment())? {
+            Some(Ok(())) => return Ok(AttemptOutcome::Landed),
+            Some(Err(err)) => {
+                return Err(format!("Transaction {} failed: {}", signature, err).into())
+            }
+            None => {}
+        }
+        if expired {
+            return Ok(AttemptOutcome::Expired);
+        }
+
+        if Instant::now() >= deadline {
+            // Until its blockhash expires the transaction can still land, so
+            // one that would be re-signed is waited on instead
+            if last_valid_block_height.is_none() {
+                return Ok(AttemptOutcome::Expired);
+            }
+            if Instant::now() >= deadline + EXPIRY_WAIT {
+                return Err(format!(
+                    "Outcome of transaction {} unknown: not confirmed and its blockhash \
+                     has not expired, so it was not re-signed",
+                    signature
+                )
+                .into());
+            }
+        }
+
+        // Leaders may drop the transaction, so keep it in flight while waiting
+        let _ = client.send_transaction_with_config(transaction, send_config(client, true));
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Retries are driven from here, so the RPC node is asked not to retry itself.
+fn send_config(client: &RpcClient, skip_preflight: bool) -> RpcSendTransactionConfig {
+    RpcSendTransactionConfig {
+        skip_preflight,
+        preflight_commitment: Some(client.commitment().commitment),
+        max_retries: Some(0),
+        ..RpcSendTransactionConfig::default()
+    }
+}
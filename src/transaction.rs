@@ -0,0 +1,427 @@
+use crate::submit;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Args, ValueEnum};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_sdk::{
+    address_lookup_table::{self, state::AddressLookupTable, AddressLookupTableAccount},
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, Message, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_instruction,
+    transaction::{Transaction, VersionedTransaction},
+};
+use solana_transaction_status::{
+    UiLoadedAddresses, UiTransactionEncoding, UiTransactionStatusMeta,
+};
+
+/// Message format of the transactions produced by `TransactionBuilder`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransactionVersion {
+    #[default]
+    Legacy,
+    V0,
+}
+
+/// Command-line options controlling how transactions are assembled.
+#[derive(Args, Clone, Default)]
+pub struct TransactionOptions {
+    /// Request this many compute units with SetComputeUnitLimit
+    #[arg(long)]
+    compute_unit_limit: Option<u32>,
+
+    /// Priority fee in micro-lamports per compute unit, set with SetComputeUnitPrice
+    #[arg(long)]
+    compute_unit_price: Option<u64>,
+
+    /// Transaction message format
+    #[arg(long, value_enum, default_value_t)]
+    tx_version: TransactionVersion,
+
+    /// Existing address lookup table to compile v0 transactions against
+    #[arg(long)]
+    lookup_table: Option<Pubkey>,
+}
+
+impl TransactionOptions {
+    pub fn builder(
+        &self,
+        client: &RpcClient,
+    ) -> Result<TransactionBuilder, Box<dyn std::error::Error>> {
+        let mut builder = TransactionBuilder {
+            compute_unit_limit: self.compute_unit_limit,
+            compute_unit_price: self.compute_unit_price,
+            version: self.tx_version,
+            lookup_tables: Vec::new(),
+        };
+        if let Some(address) = &self.lookup_table {
+            if self.tx_version != TransactionVersion::V0 {
+                return Err("--lookup-table requires --tx-version v0".into());
+            }
+            builder = builder.lookup_table(fetch_lookup_table(client, address)?);
+        }
+        Ok(builder)
+    }
+}
+
+/// Assembles escrow transactions, optionally prepending ComputeBudget instructions.
+///
+/// Transactions are legacy by default; v0 transactions may also resolve
+/// accounts through address lookup tables.
+#[derive(Clone, Default)]
+pub struct TransactionBuilder {
+    compute_unit_limit: Option<u32>,
+    compute_unit_price: Option<u64>,
+    version: TransactionVersion,
+    lookup_tables: Vec<AddressLookupTableAccount>,
+}
+
+impl TransactionBuilder {
+    pub fn version(&self) -> TransactionVersion {
+        self.version
+    }
+
+    /// Builds transactions in `version` instead of the configured format.
+    pub fn with_version(mut self, version: TransactionVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Adds a lookup table used to compile v0 messages.
+    pub fn lookup_table(mut self, lookup_table: AddressLookupTableAccount) -> Self {
+        self.lookup_tables.push(lookup_table);
+        self
+    }
+
+    /// Returns `instructions` preceded by the configured ComputeBudget instructions.
+    pub fn instructions(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        let mut all = Vec::with_capacity(instructions.len() + 2);
+        if let Some(units) = self.compute_unit_limit {
+            all.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+        }
+        if let Some(micro_lamports) = self.compute_unit_price {
+            all.push(ComputeBudgetInstruction::set_compute_unit_price(
+                micro_lamports,
+            ));
+        }
+        all.extend_from_slice(instructions);
+        all
+    }
+
+    /// Builds and signs a transaction of the configured version against the
+    /// latest blockhash.
+    pub fn build(
+        &self,
+        client: &RpcClient,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        signers: &[&Keypair],
+    ) -> Result<VersionedTransaction, Box<dyn std::error::Error>> {
+        let message = self.message(client, instructions, payer)?;
+        Ok(VersionedTransaction::try_new(message, signers)?)
+    }
+
+    /// Builds and signs a transaction against a blockhash the caller fetched,
+    /// so it can be re-signed when that blockhash expires.
+    pub fn build_with_blockhash(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        signers: &[&Keypair],
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction, Box<dyn std::error::Error>> {
+        let message = self.compile(&self.instructions(instructions), payer, recent_blockhash)?;
+        Ok(VersionedTransaction::try_new(message, signers)?)
+    }
+
+    /// Builds and signs a transaction against a durable nonce instead of a
+    /// recent blockhash. No RPC access is needed, so this can run offline.
+    pub fn sign_with_nonce(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        signers: &[&Keypair],
+        nonce_account: &Pubkey,
+        nonce_authority: &Pubkey,
+        nonce: Hash,
+    ) -> Result<VersionedTransaction, Box<dyn std::error::Error>> {
+        let message =
+            self.nonce_message(instructions, payer, nonce_account, nonce_authority, nonce)?;
+        Ok(VersionedTransaction::try_new(message, signers)?)
+    }
+
+    /// Splits `instructions` into consecutive groups that each fit in a single
+    /// transaction paid by `payer`, keeping their order.
+    ///
+    /// Each group executes atomically; ComputeBudget instructions are added
+    /// to every group and counted against the size limit.
+    pub fn pack(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<Vec<Vec<Instruction>>, Box<dyn std::error::Error>> {
+        let mut batches: Vec<Vec<Instruction>> = Vec::new();
+        let mut current: Vec<Instruction> = Vec::new();
+        for instruction in instructions {
+            current.push(instruction.clone());
+            if current.len() > 1 && self.serialized_size(&current, payer)? > PACKET_DATA_SIZE {
+                current.pop();
+                batches.push(std::mem::replace(&mut current, vec![instruction.clone()]));
+            }
+            if self.serialized_size(&current, payer)? > PACKET_DATA_SIZE {
+                return Err("Instruction does not fit in a single transaction".into());
+            }
+        }
+        if !current.is_empty() {
+            batches.push(current);
+        }
+        Ok(batches)
+    }
+
+    /// Size on the wire of a signed transaction carrying `instructions`.
+    fn serialized_size(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let message = self.compile(&self.instructions(instructions), payer, Hash::default())?;
+        let transaction = VersionedTransaction {
+            signatures: vec![
+                Signature::default();
+                usize::from(message.header().num_required_signatures)
+            ],
+            message,
+        };
+        Ok(bincode::serialized_size(&transaction)? as usize)
+    }
+
+    /// Compiles an unsigned message against the latest blockhash, for parties
+    /// that sign separately.
+    pub fn message(
+        &self,
+        client: &RpcClient,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<VersionedMessage, Box<dyn std::error::Error>> {
+        let recent_blockhash = client.get_latest_blockhash()?;
+        self.compile(&self.instructions(instructions), payer, recent_blockhash)
+    }
+
+    /// Compiles an unsigned message against a durable nonce.
+    pub fn nonce_message(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        nonce_account: &Pubkey,
+        nonce_authority: &Pubkey,
+        nonce: Hash,
+    ) -> Result<VersionedMessage, Box<dyn std::error::Error>> {
+        // The runtime only honours the nonce if advancing it is the first instruction
+        let mut all = vec![system_instruction::advance_nonce_account(
+            nonce_account,
+            nonce_authority,
+        )];
+        all.extend(self.instructions(instructions));
+        self.compile(&all, payer, nonce)
+    }
+
+    fn compile(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedMessage, Box<dyn std::error::Error>> {
+        Ok(match self.version {
+            TransactionVersion::Legacy => VersionedMessage::Legacy(Message::new_with_blockhash(
+                instructions,
+                Some(payer),
+                &recent_blockhash,
+            )),
+            TransactionVersion::V0 => VersionedMessage::V0(v0::Message::try_compile(
+                payer,
+                instructions,
+                &self.lookup_tables,
+                recent_blockhash,
+            )?),
+        })
+    }
+}
+
+/// Serializes a signed transaction as base64 so it can be carried to another machine.
+pub fn encode_transaction(
+    transaction: &VersionedTransaction,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(STANDARD.encode(bincode::serialize(transaction)?))
+}
+
+pub fn decode_transaction(
+    encoded: &str,
+) -> Result<VersionedTransaction, Box<dyn std::error::Error>> {
+    Ok(bincode::deserialize(&STANDARD.decode(encoded.trim())?)?)
+}
+
+/// Creates an address lookup table holding `addresses`, owned and paid for by
+/// `authority`, and waits until it can be used by v0 transactions.
+pub fn create_lookup_table(
+    client: &RpcClient,
+    authority: &Keypair,
+    addresses: Vec<Pubkey>,
+) -> Result<AddressLookupTableAccount, Box<dyn std::error::Error>> {
+    let recent_slot = client.get_slot()?;
+    let (create, table) = address_lookup_table::instruction::create_lookup_table(
+        authority.pubkey(),
+        authority.pubkey(),
+        recent_slot,
+    );
+    let extend = address_lookup_table::instruction::extend_lookup_table(
+        table,
+        authority.pubkey(),
+        Some(authority.pubkey()),
+        addresses,
+    );
+    let transaction = Transaction::new_signed_with_payer(
+        &[create, extend],
+        Some(&authority.pubkey()),
+        &[authority],
+        client.get_latest_blockhash()?,
+    );
+    client.send_and_confirm_transaction(&transaction)?;
+
+    // Extended addresses only become usable in the slot after the extension
+    let extended_slot = client.get_slot()?;
+    while client.get_slot()? <= extended_slot {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    fetch_lookup_table(client, &table)
+}
+
+pub fn fetch_lookup_table(
+    client: &RpcClient,
+    address: &Pubkey,
+) -> Result<AddressLookupTableAccount, Box<dyn std::error::Error>> {
+    let account = client.get_account(address)?;
+    let table = AddressLookupTable::deserialize(&account.data)?;
+    Ok(AddressLookupTableAccount {
+        key: *address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Account keys of `message` in the order instructions index them, with
+/// addresses loaded from lookup tables fetched and appended.
+pub fn resolve_account_keys(
+    client: &RpcClient,
+    message: &VersionedMessage,
+) -> Result<Vec<Pubkey>, Box<dyn std::error::Error>> {
+    let mut account_keys = message.static_account_keys().to_vec();
+    let Some(lookups) = message.address_table_lookups() else {
+        return Ok(account_keys);
+    };
+    let tables = lookups
+        .iter()
+        .map(|lookup| fetch_lookup_table(client, &lookup.account_key))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // All writable loaded addresses come first, then all readonly ones
+    for (lookup, table) in lookups.iter().zip(&tables) {
+        for &index in &lookup.writable_indexes {
+            account_keys.push(lookup_address(table, index)?);
+        }
+    }
+    for (lookup, table) in lookups.iter().zip(&tables) {
+        for &index in &lookup.readonly_indexes {
+            account_keys.push(lookup_address(table, index)?);
+        }
+    }
+    Ok(account_keys)
+}
+
+/// The address at `index` of a lookup table, which may have been shortened or
+/// deactivated since the transaction was compiled.
+fn lookup_address(table: &AddressLookupTableAccount, index: u8) -> Result<Pubkey, String> {
+    table
+        .addresses
+        .get(usize::from(index))
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "Lookup table {} has no address at index {} ({} addresses)",
+                table.key,
+                index,
+                table.addresses.len()
+            )
+        })
+}
+
+/// Fee and compute units charged for a confirmed transaction.
+pub struct TransactionCost {
+    pub fee: u64,
+    pub compute_units: Option<u64>,
+}
+
+/// Reads the fee and compute units of a confirmed transaction from its metadata.
+pub fn fetch_transaction_cost(
+    client: &RpcClient,
+    signature: &Signature,
+) -> Result<TransactionCost, Box<dyn std::error::Error>> {
+    let meta = fetch_confirmed_transaction(client, signature)?.meta;
+    Ok(TransactionCost {
+        fee: meta.fee,
+        compute_units: meta.compute_units_consumed.into(),
+    })
+}
+
+/// A confirmed transaction with every account key it references, including
+/// those loaded from lookup tables, in the order instructions index them.
+pub struct ConfirmedTransaction {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub transaction: VersionedTransaction,
+    pub account_keys: Vec<Pubkey>,
+    pub meta: UiTransactionStatusMeta,
+}
+
+pub fn fetch_confirmed_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+) -> Result<ConfirmedTransaction, Box<dyn std::error::Error>> {
+    let commitment = submit::history_commitment(client.commitment());
+    if commitment != client.commitment() {
+        // A transaction confirmed at processed may not have reached confirmed yet
+        client.poll_for_signature_with_commitment(signature, commitment)?;
+    }
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(commitment),
+        max_supported_transaction_version: Some(0),
+    };
+    let confirmed = client.get_transaction_with_config(signature, config)?;
+    let transaction = confirmed
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| format!("Transaction {} could not be decoded", signature))?;
+    let meta = confirmed
+        .transaction
+        .meta
+        .ok_or_else(|| format!("Transaction {} has no status metadata", signature))?;
+
+    let mut account_keys = transaction.message.static_account_keys().to_vec();
+    if let Some(loaded) = Option::<&UiLoadedAddresses>::from(meta.loaded_addresses.as_ref()) {
+        for key in loaded.writable.iter().chain(&loaded.readonly) {
+            account_keys.push(key.parse()?);
+        }
+    }
+
+    Ok(ConfirmedTransaction {
+        slot: confirmed.slot,
+        block_time: confirmed.block_time,
+        transaction,
+        account_keys,
+        meta,
+    })
+}
@@ -0,0 +1,209 @@
+use crate::{balances::format_sol_balance, escrow::EscrowAccount, layout};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    pubsub_client::{PubsubAccountClientSubscription, PubsubClient},
+    rpc_config::RpcAccountInfoConfig,
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+    fmt,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Which side of a subscription a watched account belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Escrow,
+    Buyer,
+    Seller,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Escrow => write!(f, "Escrow"),
+            Role::Buyer => write!(f, "Buyer"),
+            Role::Seller => write!(f, "Seller"),
+        }
+    }
+}
+
+/// A change to a watched account, with the escrow state decoded when the
+/// account is an escrow. A closed escrow arrives with zero lamports and no state.
+pub struct EscrowUpdate {
+    pub slot: u64,
+    pub role: Role,
+    pub address: Pubkey,
+    pub lamports: u64,
+    pub escrow: Option<EscrowAccount>,
+}
+
+impl EscrowUpdate {
+    pub fn print(&self) {
+        print!(
+            "[slot {}] {} {}: {} SOL",
+            self.slot,
+            self.role,
+            self.address,
+            format_sol_balance(self.lamports)
+        );
+        match &self.escrow {
+            Some(escrow_account) => println!(
+                ", payment_count={}, total_amount={}, is_active={}",
+                escrow_account.payment_count, escrow_account.total_amount, escrow_account.is_active
+            ),
+            None if self.role == Role::Escrow => println!(", closed"),
+            None => println!(),
+        }
+    }
+}
+
+/// Streams updates for an escrow PDA and its buyer and seller over websocket
+/// account subscriptions. Subscriptions are dropped with the watcher.
+pub struct EscrowWatcher {
+    _subscriptions: Vec<PubsubAccountClientSubscription>,
+    updates: Receiver<EscrowUpdate>,
+}
+
+impl EscrowWatcher {
+    /// Subscribes to every account in `accounts`. Updates only arrive for
+    /// changes made after this returns.
+    pub fn subscribe(
+        ws_url: &str,
+        program_id: &Pubkey,
+        commitment: CommitmentConfig,
+        accounts: &[(Role, Pubkey)],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (sender, updates) = mpsc::channel();
+        let mut subscriptions = Vec::with_capacity(accounts.len());
+
+        for &(role, address) in accounts {
+            let config = RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(commitment),
+                ..RpcAccountInfoConfig::default()
+            };
+            let (subscription, receiver) =
+                PubsubClient::account_subscribe(ws_url, &address, Some(config))?;
+            subscriptions.push(subscription);
+
+            // Fan each subscription into one channel; the thread ends when
+            // either the subscription or the watcher goes away
+            let sender = sender.clone();
+            let program_id = *program_id;
+            std::thread::spawn(move || {
+                while let Ok(response) = receiver.recv() {
+                    let account = response.value.decode::<Account>();
+                    let escrow = account
+                        .as_ref()
+                        .filter(|account| account.owner == program_id)
+                        .and_then(|account| layout::decode_escrow_account(&account.data).ok())
+                        .map(|decoded| decoded.account);
+                    let update = EscrowUpdate {
+                        slot: response.context.slot,
+                        role,
+                        address,
+                        lamports: response.value.lamports,
+                        escrow,
+                    };
+                    if sender.send(update).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            _subscriptions: subscriptions,
+            updates,
+        })
+    }
+
+    /// Blocks until the next update, or returns `None` once every subscription has ended.
+    pub fn next(&self) -> Option<EscrowUpdate> {
+        self.updates.recv().ok()
+    }
+
+    /// Waits for an update matching `predicate`, discarding the ones before it.
+    pub fn wait_for(
+        &self,
+        timeout: Duration,
+        predicate: impl Fn(&EscrowUpdate) -> bool,
+    ) -> Result<EscrowUpdate, Box<dyn std::error::Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.updates.recv_timeout(remaining) {
+                Ok(update) if predicate(&update) => return Ok(update),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!(
+                        "No matching account update within {} seconds",
+                        timeout.as_secs()
+                    )
+                    .into())
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("Account subscriptions closed".into())
+                }
+            }
+        }
+    }
+
+    /// Waits until the escrow account reaches a state matching `predicate`.
+    pub fn wait_for_escrow(
+        &self,
+        timeout: Duration,
+        predicate: impl Fn(&EscrowAccount) -> bool,
+    ) -> Result<EscrowAccount, Box<dyn std::error::Error>> {
+        let update = self.wait_for(timeout, |update| {
+            update.role == Role::Escrow && update.escrow.as_ref().is_some_and(&predicate)
+        })?;
+        update.escrow.ok_or_else(|| "Escrow state missing".into())
+    }
+}
+
+/// Derives the websocket endpoint from an RPC URL the way the Solana CLI does:
+/// `ws` for `http`, `wss` for `https`, and the next port when one is given.
+pub fn websocket_url(rpc_url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut url = Url::parse(rpc_url)?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme)
+        .map_err(|_| format!("Cannot derive a websocket URL from {}", rpc_url))?;
+    if let Some(port) = url.port() {
+        let port = port.checked_add(1).ok_or_else(|| {
+            format!(
+                "Cannot derive a websocket URL from {}, pass --ws-url",
+                rpc_url
+            )
+        })?;
+        url.set_port(Some(port))
+            .map_err(|_| format!("Cannot derive a websocket URL from {}", rpc_url))?;
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_websocket_urls() {
+        assert_eq!(
+            websocket_url("http://localhost:8899").unwrap(),
+            "ws://localhost:8900/"
+        );
+        assert_eq!(
+            websocket_url("https://api.devnet.solana.com").unwrap(),
+            "wss://api.devnet.solana.com/"
+        );
+    }
+
+    #[test]
+    fn rejects_the_last_port() {
+        // The port after 65535 should be rejected, not wrapped
+        assert!(websocket_url("http://localhost:65535").is_err());
+    }
+}